@@ -0,0 +1,196 @@
+#!/usr/bin/env python3
+"""从 Unicode 字符数据库（UCD）生成 src/regex/unicode/tables.rs。
+
+用法（版本号换成要升级到的版本）：
+
+    curl -LO https://www.unicode.org/Public/17.0.0/ucd/UCD.zip
+    unzip UCD.zip -d ucd
+    python3 scripts/gen_unicode_tables.py 17.0.0 ucd > src/regex/unicode/tables.rs
+
+只读 UCD 里的四个文件：UnicodeData.txt（通用类别）、Scripts.txt（文字）、
+PropList.txt（White_Space）和 DerivedCoreProperties.txt（Alphabetic）。
+升级时最好和 rustc 的 `char::UNICODE_VERSION` 用同一个版本，
+这样 `unicode` 模块里对照标准库的测试才会运行。
+"""
+
+import os
+import sys
+
+MAX_CODE_POINT = 0x10FFFF
+SURROGATES = range(0xD800, 0xE000)
+
+CATEGORIES = [
+    ("C", "Other", ["Cc", "Cf", "Cs", "Co", "Cn"]),
+    ("Cc", "Control", None),
+    ("Cf", "Format", None),
+    ("Cn", "Unassigned", None),
+    ("Co", "Private_Use", None),
+    ("L", "Letter", ["Lu", "Ll", "Lt", "Lm", "Lo"]),
+    ("Ll", "Lowercase_Letter", None),
+    ("Lm", "Modifier_Letter", None),
+    ("Lo", "Other_Letter", None),
+    ("Lt", "Titlecase_Letter", None),
+    ("Lu", "Uppercase_Letter", None),
+    ("M", "Mark", ["Mn", "Mc", "Me"]),
+    ("Mc", "Spacing_Mark", None),
+    ("Me", "Enclosing_Mark", None),
+    ("Mn", "Nonspacing_Mark", None),
+    ("N", "Number", ["Nd", "Nl", "No"]),
+    ("Nd", "Decimal_Number", None),
+    ("Nl", "Letter_Number", None),
+    ("No", "Other_Number", None),
+    ("P", "Punctuation", ["Pc", "Pd", "Ps", "Pe", "Pi", "Pf", "Po"]),
+    ("Pc", "Connector_Punctuation", None),
+    ("Pd", "Dash_Punctuation", None),
+    ("Pe", "Close_Punctuation", None),
+    ("Pf", "Final_Punctuation", None),
+    ("Pi", "Initial_Punctuation", None),
+    ("Po", "Other_Punctuation", None),
+    ("Ps", "Open_Punctuation", None),
+    ("S", "Symbol", ["Sm", "Sc", "Sk", "So"]),
+    ("Sc", "Currency_Symbol", None),
+    ("Sk", "Modifier_Symbol", None),
+    ("Sm", "Math_Symbol", None),
+    ("So", "Other_Symbol", None),
+    ("Z", "Separator", ["Zs", "Zl", "Zp"]),
+    ("Zl", "Line_Separator", None),
+    ("Zp", "Paragraph_Separator", None),
+    ("Zs", "Space_Separator", None),
+]
+
+
+def data_lines(path):
+    """去掉注释和空行，按 ';' 切开每一行"""
+    with open(path, encoding="utf-8") as f:
+        for line in f:
+            line = line.split("#", 1)[0].strip()
+            if line:
+                yield [field.strip() for field in line.split(";")]
+
+
+def code_points(field):
+    """`0041` 或 `0041..005A`"""
+    lo, _, hi = field.partition("..")
+    return range(int(lo, 16), int(hi or lo, 16) + 1)
+
+
+def general_categories(ucd):
+    """每个码点的通用类别，UnicodeData.txt 里没有列出的都是 Cn"""
+    gc = ["Cn"] * (MAX_CODE_POINT + 1)
+    first = None
+    for fields in data_lines(os.path.join(ucd, "UnicodeData.txt")):
+        cp, name, cat = int(fields[0], 16), fields[1], fields[2]
+        if name.endswith(", First>"):
+            first = cp
+            continue
+        # `<..., First>` 和 `<..., Last>` 两行之间的码点都是同一个类别
+        start = first if name.endswith(", Last>") else cp
+        for c in range(start, cp + 1):
+            gc[c] = cat
+        first = None
+    return gc
+
+
+def property_sets(path, names):
+    """PropList.txt 这类 `码点; 属性名` 格式的文件"""
+    sets = {name: set() for name in names}
+    for fields in data_lines(path):
+        if fields[1] in sets:
+            sets[fields[1]].update(code_points(fields[0]))
+    return sets
+
+
+def scripts(ucd):
+    """文字名到码点集合，按名字排序"""
+    sets = {}
+    for fields in data_lines(os.path.join(ucd, "Scripts.txt")):
+        sets.setdefault(fields[1], set()).update(code_points(fields[0]))
+    return dict(sorted(sets.items()))
+
+
+def ranges(cps):
+    """升序、合并后的区间。`char` 里没有代理区的码点，区间可以跨过它"""
+    out = []
+    for c in sorted(c for c in cps if c not in SURROGATES):
+        if out and (out[-1][1] + 1 == c or (out[-1][1] == 0xD7FF and c == 0xE000)):
+            out[-1][1] = c
+        else:
+            out.append([c, c])
+    return out
+
+
+def table(name, cps):
+    lines = [f"pub const {name}: &[(char, char)] = &["]
+    line = "   "
+    for lo, hi in ranges(cps):
+        item = f"('\\u{{{lo:x}}}', '\\u{{{hi:x}}}'),"
+        if len(line) + 1 + len(item) > 100:
+            lines.append(line)
+            line = "   "
+        line += " " + item
+    if line.strip():
+        lines.append(line)
+    lines.append("];")
+    return "\n".join(lines)
+
+
+def normalize(name):
+    return name.replace("_", "").replace(" ", "").replace("-", "").lower()
+
+
+def generate(version, ucd):
+    gc = general_categories(ucd)
+    by_category = {}
+    for c, cat in enumerate(gc):
+        by_category.setdefault(cat, set()).add(c)
+    white_space = property_sets(os.path.join(ucd, "PropList.txt"), ["White_Space"])
+    alphabetic = property_sets(os.path.join(ucd, "DerivedCoreProperties.txt"), ["Alphabetic"])
+    numeric = set().union(*(by_category.get(cat, set()) for cat in ["Nd", "Nl", "No"]))
+
+    major, minor, update = (int(part) for part in version.split("."))
+    out = [
+        f"// 由 scripts/gen_unicode_tables.py 从 Unicode {version} 的 UCD 生成，不要手工修改，",
+        "// 重新生成的命令见脚本开头的说明。",
+        "// 每个表里的区间按码点升序排列、互不重叠，代理区的码点不会出现在 `char` 里，区间可以跨过它。",
+        "",
+        "/// 生成这些表用的 Unicode 版本，和 `char::UNICODE_VERSION` 的格式相同",
+        f"pub const UNICODE_VERSION: (u8, u8, u8) = ({major}, {minor}, {update});",
+        "",
+        "/// `\\w`：字母、数字和 '_'，必须和 `char::is_alphanumeric` 保持一致：",
+        "/// `\\w` 原来就是这样定义的，分组名也还按 `is_alphanumeric` 解析",
+        table("PERL_WORD", alphabetic["Alphabetic"] | numeric | {ord("_")}),
+        "",
+        "/// `\\s`：White_Space 属性",
+        table("WHITE_SPACE", white_space["White_Space"]),
+        "",
+    ]
+
+    names = []
+    for short, long, parts in CATEGORIES:
+        cps = set().union(*(by_category.get(cat, set()) for cat in parts or [short]))
+        const = "GC_" + long.upper()
+        out += [table(const, cps), ""]
+        names += [(normalize(short), const), (normalize(long), const)]
+
+    script_names = []
+    for script, cps in scripts(ucd).items():
+        const = "SC_" + script.upper()
+        out += [table(const, cps), ""]
+        script_names.append((normalize(script), const))
+
+    # 按规范化后的名字排序，查找时二分
+    out.append("/// 通用类别，名字已经去掉了 '_'、空格和 '-' 并转成小写，按名字排序")
+    out.append("pub const GENERAL_CATEGORY: &[(&str, &[(char, char)])] = &[")
+    out += [f'    ("{n}", {c}),' for n, c in sorted(set(names))]
+    out += ["];", ""]
+    out.append("/// 文字（Script），名字的规范化方式同上")
+    out.append("pub const SCRIPT: &[(&str, &[(char, char)])] = &[")
+    out += [f'    ("{n}", {c}),' for n, c in sorted(set(script_names))]
+    out.append("];")
+    return "\n".join(out) + "\n"
+
+
+if __name__ == "__main__":
+    if len(sys.argv) != 3:
+        sys.exit("usage: gen_unicode_tables.py <unicode-version> <ucd-dir>")
+    sys.stdout.write(generate(sys.argv[1], sys.argv[2]))
@@ -1,14 +1,8 @@
-mod regex;
-
-use anyhow::Error;
-use regex::*;
-use std::char;
-use std::collections::HashSet;
+use codecrafters_grep::regex::{self, Lang, ParseError, Regex};
 use std::env;
+use std::ffi::OsString;
 use std::io;
-use std::iter::Peekable;
 use std::process;
-use std::str::Chars;
 
 /* fn match_pattern(input_line: &str, pattern: &str) -> bool {
     if pattern.chars().count() == 1 {
@@ -40,21 +34,46 @@ use std::str::Chars;
  */
 
 // Usage: echo <input_text> | your_program.sh -E <pattern>
-fn main() -> Result<(), Error> {
+fn main() {
     // You can use print statements as follows for debugging, they'll be visible when running tests.
     // eprintln!("Logs from your program will appear here!");
 
-    if env::args().nth(1).unwrap() != "-E" {
+    // env::args() 遇到不是 UTF-8 的参数会 panic
+    let args: Vec<String> = match env::args_os().map(OsString::into_string).collect() {
+        Ok(args) => args,
+        Err(arg) => {
+            eprintln!("invalid UTF-8 in argument: {:?}", arg);
+            process::exit(2)
+        }
+    };
+    if args.get(1).map(String::as_str) != Some("-E") {
         println!("Expected first argument to be '-E'");
         process::exit(1);
     }
+    let Some(pattern) = args.get(2) else {
+        eprintln!("Usage: echo <input_text> | {} -E <pattern>", args[0]);
+        process::exit(2)
+    };
 
-    let pattern = env::args().nth(2).unwrap();
-    let mut input_line = String::new();
+    regex::set_lang(locale_lang());
 
-    io::stdin().read_line(&mut input_line).unwrap();
+    let mut input_line = String::new();
+    if let Err(err) = io::stdin().read_line(&mut input_line) {
+        eprintln!("{}", err);
+        process::exit(2)
+    }
 
-    let re = Regex::new(&pattern)?;
+    let re = match Regex::new(pattern) {
+        Ok(re) => re,
+        Err(err) => {
+            // 模式串有语法错误时标出出错的位置，和 grep 一样以 2 退出
+            match err.downcast_ref::<ParseError>() {
+                Some(err) => eprintln!("error[{}]: {:#}", err.code(), err),
+                None => eprintln!("{:#}", err),
+            }
+            process::exit(2)
+        }
+    };
     // Uncomment this block to pass the first stage
     if re.is_match(input_line.as_str()) {
         println!("{input_line}");
@@ -63,3 +82,12 @@ fn main() -> Result<(), Error> {
         process::exit(1)
     }
 }
+
+/// 和 gettext 一样，LC_ALL、LC_MESSAGES、LANG 中第一个非空的决定错误信息的语言
+fn locale_lang() -> Lang {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .map_or(Lang::English, |locale| Lang::from_locale(&locale))
+}
@@ -1,69 +1,340 @@
+pub mod ast;
+mod class;
+mod compiler;
+mod dfa;
+mod fold;
+#[cfg(test)]
+mod fuzz;
 mod input;
 mod ir;
+mod lang;
 mod parser;
+mod pikevm;
 mod result;
+mod unicode;
 mod vm;
 
-use crate::regex::{input::Text, ir::Inst, parser::Parser, vm::VM};
+use crate::regex::{
+    dfa::{Cache, Dfa},
+    input::Text,
+    ir::Inst,
+    parser::{Flags, Parser},
+    pikevm::PikeVM,
+    vm::VM,
+};
 
 use anyhow::Error;
 use std::{
-    char,
-    collections::{HashMap, HashSet},
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, TryLockError},
 };
-use thiserror::{self, Error};
 
-#[derive(Debug, Error)]
-pub enum RegexError {
-    #[error("'^'只能出现在字符串开头")]
-    MisplacedAnchor,
+use crate::regex::result::MatchResult;
+pub use crate::regex::{
+    compiler::DEFAULT_SIZE_LIMIT,
+    lang::{lang, set_lang, Lang},
+    parser::{ErrorKind, ParseError},
+    result::{Captures, Match},
+    unicode::UNICODE_VERSION,
+};
 
-    #[error("非法的转义字符")]
-    UnknownEscape,
+pub struct Regex {
+    instrs: Vec<Inst>,
+    // 下标是组号，第 0 组是整个匹配
+    group_names: Vec<Option<String>>,
+    name_index: Arc<HashMap<String, usize>>,
+    // 多个线程共用一个 Regex 时，同一时刻只有一个线程能用 DFA 的缓存
+    dfa_cache: Mutex<Cache>,
+}
 
-    #[error("未闭合的分组")]
-    UnclosedCharClass,
+/// 带编译选项构造 `Regex`
+pub struct RegexBuilder {
+    pattern: String,
+    flags: Flags,
+    size_limit: usize,
 }
 
-pub struct Regex {
-    instrs: Vec<Inst>,
+impl RegexBuilder {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            flags: Flags::default(),
+            size_limit: DEFAULT_SIZE_LIMIT,
+        }
+    }
+
+    /// 忽略大小写，相当于在模式串开头写 `(?i)`
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut Self {
+        self.flags.case_insensitive = yes;
+        self
+    }
+
+    /// 多行模式，相当于在模式串开头写 `(?m)`：`^`、`$` 也匹配每一行的开头和结尾
+    pub fn multi_line(&mut self, yes: bool) -> &mut Self {
+        self.flags.multi_line = yes;
+        self
+    }
+
+    /// 编译后的程序大小的上限，超过时 `build` 返回错误，默认是 `DEFAULT_SIZE_LIMIT`。
+    /// 大小大致是指令数，字符类还要加上区间个数
+    pub fn size_limit(&mut self, limit: usize) -> &mut Self {
+        self.size_limit = limit;
+        self
+    }
+
+    pub fn build(&self) -> Result<Regex, Error> {
+        let parser = Parser::with_flags(&self.pattern, self.flags);
+        Regex::from_parser(parser, self.size_limit)
+    }
 }
 
 impl Regex {
     pub fn new(pattern: &str) -> Result<Self, Error> {
-        let instrs = Parser::new(pattern).compile()?;
-        Ok(Self { instrs })
+        Self::from_parser(Parser::new(pattern), DEFAULT_SIZE_LIMIT)
     }
 
+    fn from_parser(parser: Parser, size_limit: usize) -> Result<Self, Error> {
+        let program = parser.compile(size_limit)?;
+        let name_index = program
+            .group_names
+            .iter()
+            .enumerate()
+            .filter_map(|(num, name)| Some((name.clone()?, num)))
+            .collect();
+        Ok(Self {
+            instrs: program.instrs,
+            group_names: program.group_names,
+            name_index: Arc::new(name_index),
+            dfa_cache: Mutex::new(Cache::default()),
+        })
+    }
+
+    /// 能用惰性 DFA 时先用 DFA。DFA 的状态缓存在各线程之间共享，
+    /// 缓存正被别的线程占用时不等待，这次改用 Pike VM，结果相同
     pub fn is_match(&self, text: &str) -> bool {
         let input = Text::new(text);
-        // let mut vm = VM::new(&self.instrs);
-
-        match self.instrs.get(0) {
-            Some(Inst::Start) => {
-                let mut vm = VM::new(&self.instrs);
-                vm.run(1, &input, 0)
+        if Dfa::supports(&self.instrs) {
+            if let Some(mut cache) = self.try_dfa_cache() {
+                return Dfa::new(&self.instrs, &mut cache).is_match(&input);
             }
-            Some(_) => {
-                let mut text_cursor = 0;
-                while !input.is_end(text_cursor) {
-                    let mut vm = VM::new(&self.instrs);
-                    if vm.run(0, &input, text_cursor) {
-                        return true;
+        }
+        if PikeVM::supports(&self.instrs) {
+            return PikeVM::new(&self.instrs).is_match(&input);
+        }
+        self.find_at(&input, 0).is_some()
+    }
+
+    /// 拿到 DFA 的缓存，被占用时返回 None。持有锁的线程 panic 过也照常使用，
+    /// 缓存里的状态只是可以重建的中间结果
+    fn try_dfa_cache(&self) -> Option<MutexGuard<'_, Cache>> {
+        match self.dfa_cache.try_lock() {
+            Ok(cache) => Some(cache),
+            Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    /// 返回最左边的匹配，同一起点上按分支优先级取第一个
+    pub fn find<'t>(&self, text: &'t str) -> Option<Match<'t>> {
+        let input = Text::new(text);
+        let (start, end) = self.find_at(&input, 0)?;
+        Some(Match::new(text, start, end))
+    }
+
+    /// 依次返回互不重叠的匹配
+    pub fn find_iter<'r, 't>(&'r self, text: &'t str) -> Matches<'r, 't> {
+        Matches {
+            regex: self,
+            text,
+            searcher: Searcher::new(),
+        }
+    }
+
+    /// 返回最左边的匹配及其中各个捕获组的位置
+    pub fn captures<'t>(&self, text: &'t str) -> Option<Captures<'t>> {
+        let input = Text::new(text);
+        let result = self.captures_at(&input, 0)?;
+        Some(Captures::new(text, result, self.name_index.clone()))
+    }
+
+    /// 依次返回互不重叠的匹配的捕获组
+    pub fn captures_iter<'r, 't>(&'r self, text: &'t str) -> CaptureMatches<'r, 't> {
+        CaptureMatches {
+            regex: self,
+            text,
+            searcher: Searcher::new(),
+        }
+    }
+
+    /// 捕获组的数量，包括第 0 组
+    pub fn captures_len(&self) -> usize {
+        self.group_names.len()
+    }
+
+    /// 按组号依次返回各捕获组的名字，第 0 组和没有名字的组为 None
+    pub fn capture_names(&self) -> CaptureNames<'_> {
+        CaptureNames(self.group_names.iter())
+    }
+
+    fn find_at(&self, input: &Text, start: usize) -> Option<(usize, usize)> {
+        let mut slots = [None; 2];
+        if !self.search(input, start, &mut slots) {
+            return None;
+        }
+        Some((slots[0]?, slots[1]?))
+    }
+
+    fn captures_at(&self, input: &Text, start: usize) -> Option<MatchResult> {
+        let mut slots = vec![None; self.captures_len() * 2];
+        if !self.search(input, start, &mut slots) {
+            return None;
+        }
+        Some(MatchResult::from_slots(&slots))
+    }
+
+    /// 从 start 开始找最左边的匹配，把捕获位置写进 slots
+    fn search(&self, input: &Text, start: usize, slots: &mut [Option<usize>]) -> bool {
+        if PikeVM::supports(&self.instrs) {
+            return PikeVM::new(&self.instrs).search(input, start, slots);
+        }
+        self.search_backtrack(input, start, slots)
+    }
+
+    /// 含有反向引用等结构时，只能逐个起点用回溯的 VM 尝试。
+    /// 每次失败后 VM 都已经撤销了所有修改，可以接着用
+    fn search_backtrack(&self, input: &Text, start: usize, slots: &mut [Option<usize>]) -> bool {
+        let mut vm = VM::new(&self.instrs);
+        let mut text_cursor = start;
+        loop {
+            if vm.run(0, input, text_cursor) {
+                for (num, pair) in slots.chunks_mut(2).enumerate() {
+                    let group = if num == 0 {
+                        vm.end().map(|end| (text_cursor, end))
+                    } else {
+                        vm.group(num)
+                    };
+                    pair.fill(None);
+                    if let (Some((s, e)), [start, end]) = (group, pair) {
+                        *start = Some(s);
+                        *end = Some(e);
                     }
-                    text_cursor = input.next_cursor_unsafe(text_cursor);
                 }
-                false
+                return true;
+            }
+            // '^' 和 \A 只在文本开头成立，多行模式的 '^' 编译成 StartLine，不在此列
+            if let Some(Inst::Start) = self.instrs.first() {
+                return false;
+            }
+            match input.next_cursor(text_cursor) {
+                Some(next) => text_cursor = next,
+                None => return false,
             }
-            None => true,
         }
     }
 }
 
+/// 记录迭代到哪里了，保证相邻两次匹配不重叠
+struct Searcher {
+    cursor: Option<usize>,
+    last_end: Option<usize>,
+}
+
+impl Searcher {
+    fn new() -> Self {
+        Self {
+            cursor: Some(0),
+            last_end: None,
+        }
+    }
+
+    /// search 从给定位置开始找一个匹配，span 取出它的起止位置。
+    /// find_iter 只需要第 0 组，不必为每个匹配记录所有捕获组
+    fn next<T>(
+        &mut self,
+        input: &Text,
+        search: impl Fn(usize) -> Option<T>,
+        span: impl Fn(&T) -> Option<(usize, usize)>,
+    ) -> Option<T> {
+        loop {
+            let cursor = self.cursor?;
+            let Some(result) = search(cursor) else {
+                self.cursor = None;
+                return None;
+            };
+            let (start, end) = span(&result)?;
+            if start == end && Some(end) == self.last_end {
+                // 紧跟在上一个匹配后面的空匹配不算，跳过一个字符重新找
+                self.cursor = input.next_cursor(end);
+                continue;
+            }
+            self.cursor = Some(end);
+            self.last_end = Some(end);
+            return Some(result);
+        }
+    }
+}
+
+/// `Regex::find_iter` 返回的迭代器
+pub struct Matches<'r, 't> {
+    regex: &'r Regex,
+    text: &'t str,
+    searcher: Searcher,
+}
+
+impl<'r, 't> Iterator for Matches<'r, 't> {
+    type Item = Match<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (regex, input) = (self.regex, Text::new(self.text));
+        let (start, end) =
+            self.searcher
+                .next(&input, |at| regex.find_at(&input, at), |&span| Some(span))?;
+        Some(Match::new(self.text, start, end))
+    }
+}
+
+/// `Regex::capture_names` 返回的迭代器
+pub struct CaptureNames<'r>(std::slice::Iter<'r, Option<String>>);
+
+impl<'r> Iterator for CaptureNames<'r> {
+    type Item = Option<&'r str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|name| name.as_deref())
+    }
+}
+
+/// `Regex::captures_iter` 返回的迭代器
+pub struct CaptureMatches<'r, 't> {
+    regex: &'r Regex,
+    text: &'t str,
+    searcher: Searcher,
+}
+
+impl<'r, 't> Iterator for CaptureMatches<'r, 't> {
+    type Item = Captures<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (regex, input) = (self.regex, Text::new(self.text));
+        let result = self.searcher.next(
+            &input,
+            |at| regex.captures_at(&input, at),
+            |result| result.group(0),
+        )?;
+        Some(Captures::new(
+            self.text,
+            result,
+            self.regex.name_index.clone(),
+        ))
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use anyhow::{Context, Error};
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_compile_zero_or_more() -> Result<(), Error> {
@@ -229,6 +500,17 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_backreference_after_repeated_group() -> Result<(), Error> {
+        // 重复进入的分组写回自己的位置，后面分组的编号不会错位
+        let reg = Regex::new(r"(a|b)+-(c)\2").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("ab-cc"), true);
+        // 反向引用按字节数前进
+        let reg = Regex::new(r"(\w)\1x").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("ééx"), true);
+        Ok(())
+    }
+
     #[test]
     fn test_the_n_quantifier() -> Result<(), Error> {
         let reg = Regex::new(r"applee{2}").context("编译模式串出错")?;
@@ -255,4 +537,909 @@ mod tests {
         assert_eq!(reg.is_match("btpb"), true);
         Ok(())
     }
+
+    #[test]
+    fn test_find() -> Result<(), Error> {
+        let reg = Regex::new(r"\d+ (cat|dog)s?").context("编译模式串出错")?;
+        let m = reg.find("I see 42 dogs and 1 cat").unwrap();
+        assert_eq!((m.start(), m.end()), (6, 13));
+        assert_eq!(m.as_str(), "42 dogs");
+        assert!(reg.find("no pets").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_unicode_offsets() -> Result<(), Error> {
+        let reg = Regex::new(r"g.+l").context("编译模式串出错")?;
+        let m = reg.find("øögoøö0Ogol").unwrap();
+        assert_eq!(m.start(), 4);
+        assert_eq!(m.as_str(), "goøö0Ogol");
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_backreference() -> Result<(), Error> {
+        let reg = Regex::new(r"(\w+) \1").context("编译模式串出错")?;
+        let m = reg.find("say bye bye now").unwrap();
+        assert_eq!(m.as_str(), "bye bye");
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_iter() -> Result<(), Error> {
+        let reg = Regex::new(r"\d+").context("编译模式串出错")?;
+        let found: Vec<&str> = reg.find_iter("a1b22c333").map(|m| m.as_str()).collect();
+        assert_eq!(found, vec!["1", "22", "333"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_iter_empty_matches() -> Result<(), Error> {
+        let reg = Regex::new(r"a*").context("编译模式串出错")?;
+        let found: Vec<(usize, usize)> = reg
+            .find_iter("baaac")
+            .map(|m| (m.start(), m.end()))
+            .collect();
+        assert_eq!(found, vec![(0, 0), (1, 4), (5, 5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_captures() -> Result<(), Error> {
+        let reg = Regex::new(r"(\d+) (\w+) (cat|dog)(s)?").context("编译模式串出错")?;
+        let caps = reg.captures("I see 3 red cats and 1 dog").unwrap();
+        assert_eq!(caps.len(), 5);
+        assert_eq!(&caps[0], "3 red cats");
+        assert_eq!(&caps[1], "3");
+        assert_eq!(&caps[2], "red");
+        assert_eq!(caps.get(3).map(|m| m.range()), Some(12..15));
+        assert_eq!(&caps[4], "s");
+        Ok(())
+    }
+
+    #[test]
+    fn test_captures_unmatched_group() -> Result<(), Error> {
+        let reg = Regex::new(r"((a)|(b))").context("编译模式串出错")?;
+        let caps = reg.captures("b").unwrap();
+        assert_eq!(&caps[1], "b");
+        assert!(caps.get(2).is_none());
+        assert_eq!(&caps[3], "b");
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "no match for group 2")]
+    fn test_captures_index_unmatched() {
+        let reg = Regex::new(r"((a)|(b))").unwrap();
+        let caps = reg.captures("b").unwrap();
+        let _ = &caps[2];
+    }
+
+    #[test]
+    fn test_captures_backreference() -> Result<(), Error> {
+        let reg = Regex::new(r"('(cat) and \2') is the same as \1").context("编译模式串出错")?;
+        let caps = reg
+            .captures("'cat and cat' is the same as 'cat and cat'")
+            .unwrap();
+        assert_eq!(&caps[1], "'cat and cat'");
+        assert_eq!(&caps[2], "cat");
+        Ok(())
+    }
+
+    #[test]
+    fn test_captures_iter() -> Result<(), Error> {
+        let reg = Regex::new(r"(\w+):(\d+)").context("编译模式串出错")?;
+        let pairs: Vec<(String, String)> = reg
+            .captures_iter("a:1, bb:22, c:x, d:4")
+            .map(|caps| (caps[1].to_string(), caps[2].to_string()))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("bb".to_string(), "22".to_string()),
+                ("d".to_string(), "4".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dfa_cache_busy() -> Result<(), Error> {
+        let reg = Regex::new(r"a[bc]+d").context("编译模式串出错")?;
+        // 缓存被占用时改用 Pike VM，不会等待
+        let _cache = reg.dfa_cache.lock().unwrap();
+        assert_eq!(reg.is_match("xabcbd"), true);
+        assert_eq!(reg.is_match("xabd_"), true);
+        assert_eq!(reg.is_match("xad"), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_is_match() -> Result<(), Error> {
+        let reg = Regex::new(r"(foo|bar)\d+$").context("编译模式串出错")?;
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..1000 {
+                        assert_eq!(reg.is_match(&format!("x-foo{}", i)), true);
+                        assert_eq!(reg.is_match(&format!("x-baz{}", i)), false);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_named_groups() -> Result<(), Error> {
+        let reg =
+            Regex::new(r"(?P<year>\d\d\d\d)-(?<month>\d\d)-(\d\d)").context("编译模式串出错")?;
+        let names: Vec<Option<&str>> = reg.capture_names().collect();
+        assert_eq!(names, vec![None, Some("year"), Some("month"), None]);
+
+        let caps = reg.captures("date: 2024-05-17").unwrap();
+        assert_eq!(&caps["year"], "2024");
+        assert_eq!(caps.name("month").map(|m| m.as_str()), Some("05"));
+        assert_eq!(&caps[3], "17");
+        assert!(caps.name("day").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_named_backreference() -> Result<(), Error> {
+        let reg = Regex::new(r"(?<word>\w+) and \k<word>").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("cat and cat"), true);
+        assert_eq!(reg.is_match("cat and dog"), false);
+        // 引用后面才定义的分组，在循环的下一圈才有内容
+        let reg = Regex::new(r"(?:\k<x>b|(?<x>a))+").context("编译模式串出错")?;
+        assert_eq!(reg.find("aab").unwrap().as_str(), "aab");
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_group_names() {
+        assert!(Regex::new(r"(?<1a>x)").is_err());
+        assert!(Regex::new(r"(?<>x)").is_err());
+        assert!(Regex::new(r"(?<a>x)(?P<a>y)").is_err());
+        assert!(Regex::new(r"(?<a>x)\k<b>").is_err());
+        assert!(Regex::new(r"(?<a x)").is_err());
+    }
+
+    #[test]
+    fn test_non_capturing_groups() -> Result<(), Error> {
+        let reg = Regex::new(r"(?:cat|dog)s? and (\w+) (?:again )?\1").context("编译模式串出错")?;
+        assert_eq!(reg.captures_len(), 2);
+        let caps = reg.captures("dogs and birds birds").unwrap();
+        assert_eq!(&caps[0], "dogs and birds birds");
+        assert_eq!(&caps[1], "birds");
+        assert_eq!(reg.is_match("cat and fish again fish"), true);
+        assert_eq!(reg.is_match("cat and fish again cat"), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_capturing_repeat() -> Result<(), Error> {
+        let reg = Regex::new(r"^(?:ab){2,3}(c)$").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("ababc"), true);
+        assert_eq!(reg.is_match("abababababc"), false);
+        assert_eq!(
+            reg.captures("abababc").unwrap().get(1).map(|m| m.start()),
+            Some(6)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_quantifiers() -> Result<(), Error> {
+        let reg = Regex::new(r#"".*?""#).context("编译模式串出错")?;
+        let m = reg.find(r#"name: "cat", kind: "pet""#).unwrap();
+        assert_eq!(m.as_str(), r#""cat""#);
+
+        let greedy = Regex::new(r#"".*""#).context("编译模式串出错")?;
+        let m = greedy.find(r#"name: "cat", kind: "pet""#).unwrap();
+        assert_eq!(m.as_str(), r#""cat", kind: "pet""#);
+
+        let reg = Regex::new(r"a+?").context("编译模式串出错")?;
+        assert_eq!(reg.find("aaa").unwrap().as_str(), "a");
+        let reg = Regex::new(r"ab??").context("编译模式串出错")?;
+        assert_eq!(reg.find("abb").unwrap().as_str(), "a");
+        let reg = Regex::new(r"a{2,4}?").context("编译模式串出错")?;
+        assert_eq!(reg.find("aaaaa").unwrap().as_str(), "aa");
+        let reg = Regex::new(r"a{2,}?").context("编译模式串出错")?;
+        assert_eq!(reg.find("aaaaa").unwrap().as_str(), "aa");
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_quantifier_with_backreference() -> Result<(), Error> {
+        let reg = Regex::new(r"(\w+?)\1").context("编译模式串出错")?;
+        let caps = reg.captures("aaaa").unwrap();
+        assert_eq!(&caps[0], "aa");
+        assert_eq!(&caps[1], "a");
+
+        let reg = Regex::new(r"(\w+)\1").context("编译模式串出错")?;
+        assert_eq!(&reg.captures("aaaa").unwrap()[1], "aa");
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_group() -> Result<(), Error> {
+        let reg = Regex::new(r"a(?>bc|b)c").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("abcc"), true);
+        assert_eq!(reg.is_match("abc"), false);
+
+        let reg = Regex::new(r"^(?>(\w+)),").context("编译模式串出错")?;
+        assert_eq!(&reg.captures("ab,c").unwrap()[1], "ab");
+        Ok(())
+    }
+
+    #[test]
+    fn test_possessive_quantifiers() -> Result<(), Error> {
+        let reg = Regex::new(r"a*+a").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("aaaa"), false);
+        let reg = Regex::new(r"a++b").context("编译模式串出错")?;
+        assert_eq!(reg.find("xaaab").unwrap().as_str(), "aaab");
+        let reg = Regex::new(r"ab?+b").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("ab"), false);
+        assert_eq!(reg.is_match("abb"), true);
+        let reg = Regex::new(r"a{1,3}+a").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("aaa"), false);
+        assert_eq!(reg.is_match("aaaa"), true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_possessive_stops_catastrophic_backtracking() -> Result<(), Error> {
+        let reg = Regex::new(r"^(?:\w++)*+:").context("编译模式串出错")?;
+        let input = format!("{}!", "a".repeat(200));
+        assert_eq!(reg.is_match(&input), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookahead() -> Result<(), Error> {
+        let reg = Regex::new(r"\w+(?=:)").context("编译模式串出错")?;
+        assert_eq!(reg.find("key: value").unwrap().as_str(), "key");
+        let reg = Regex::new(r"foo(?!bar)\w*").context("编译模式串出错")?;
+        assert_eq!(reg.find("foobar foobaz").unwrap().as_str(), "foobaz");
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookbehind() -> Result<(), Error> {
+        let reg = Regex::new(r"(?<=<)\w+(?=>)").context("编译模式串出错")?;
+        assert_eq!(reg.find("a <tag> b").unwrap().as_str(), "tag");
+        let reg = Regex::new(r"(?<!-)\d+").context("编译模式串出错")?;
+        assert_eq!(reg.find("-12 34").unwrap().as_str(), "2");
+        let reg = Regex::new(r"(?<=ab|c)d").context("编译模式串出错")?;
+        assert_eq!(reg.find("xbd abd").unwrap().range(), 6..7);
+        assert_eq!(reg.is_match("cd"), true);
+        // 每个位置都要执行一次后顾断言的子程序，越过断言处的路径要马上放弃
+        let text = "a".repeat(50_000);
+        let reg = Regex::new(r"(?<=\w{0,1000})x").context("编译模式串出错")?;
+        let started = Instant::now();
+        assert!(reg.find(&text).is_none());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(reg.find("ab x").unwrap().range(), 3..4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookaround_captures() -> Result<(), Error> {
+        let reg = Regex::new(r"(?=(\w+))\w+:\1").context("编译模式串出错")?;
+        let caps = reg.captures("ab:ab").unwrap();
+        assert_eq!(&caps[1], "ab");
+        let reg = Regex::new(r"(?!(x))\w").context("编译模式串出错")?;
+        let caps = reg.captures("a").unwrap();
+        assert!(caps.get(1).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbounded_lookbehind() {
+        assert!(Regex::new(r"(?<=a+)b").is_err());
+        assert!(Regex::new(r"(?<!a*)b").is_err());
+        assert!(Regex::new(r"(?<=(a)\1)b").is_err());
+        assert!(Regex::new(r"(?<=a{2,5})b").is_ok());
+    }
+
+    #[test]
+    fn test_word_boundary() -> Result<(), Error> {
+        let reg = Regex::new(r"\bcat\b").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("a cat here"), true);
+        assert_eq!(reg.is_match("cat"), true);
+        assert_eq!(reg.is_match("concatenate"), false);
+        assert_eq!(reg.is_match("cats"), false);
+        assert_eq!(reg.find("bobcat, cat").unwrap().range(), 8..11);
+        assert_eq!(reg.is_match("été cat_"), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_word_boundary() -> Result<(), Error> {
+        let reg = Regex::new(r"\Bcat\B").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("concatenate"), true);
+        assert_eq!(reg.is_match("a cat"), false);
+        let reg = Regex::new(r"\B").context("编译模式串出错")?;
+        assert_eq!(reg.is_match(""), true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_word_boundary_unicode() -> Result<(), Error> {
+        let reg = Regex::new(r"\b\w+\b").context("编译模式串出错")?;
+        assert_eq!(reg.find("  héllo!").unwrap().as_str(), "héllo");
+        let reg = Regex::new(r"(\w)\b\1").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("aa"), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_inline_flags() -> Result<(), Error> {
+        let reg = Regex::new(r"(?i)hello").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("say HeLLo"), true);
+        let reg = Regex::new(r"a(?i)b(?-i)c").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("aBc"), true);
+        assert_eq!(reg.is_match("aBC"), false);
+        assert_eq!(reg.is_match("ABc"), false);
+        let reg = Regex::new(r"(?i:[a-c]x)y").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("BXy"), true);
+        assert_eq!(reg.is_match("BXY"), false);
+        let reg = Regex::new(r"(a(?i)b)c").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("aBc"), true);
+        assert_eq!(reg.is_match("aBC"), false);
+        assert!(Regex::new(r"(?q)a").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flag_dot_all_and_multi_line() -> Result<(), Error> {
+        let reg = Regex::new(r"a.b").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("a\nb"), false);
+        let reg = Regex::new(r"(?s)a.b").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("a\nb"), true);
+        let reg = Regex::new(r"^b").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("a\nb"), false);
+        let reg = Regex::new(r"(?m)^b\w").context("编译模式串出错")?;
+        assert_eq!(reg.find("ab\nbc").unwrap().range(), 3..5);
+        let reg = Regex::new(r"(?m)a$").context("编译模式串出错")?;
+        assert_eq!(reg.find("ba\nca").unwrap().range(), 1..2);
+        assert_eq!(reg.is_match("ab\ncb"), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_flag_verbose() -> Result<(), Error> {
+        let reg = Regex::new("(?x) a b* # 注释 \n c").context("编译模式串出错")?;
+        assert_eq!(reg.find("xabbc").unwrap().as_str(), "abbc");
+        assert_eq!(reg.is_match("a c"), false);
+        let reg = Regex::new(r"(?x)a\ b").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("a b"), true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_insensitive_option() -> Result<(), Error> {
+        let reg = RegexBuilder::new("error")
+            .case_insensitive(true)
+            .build()
+            .context("编译模式串出错")?;
+        assert_eq!(reg.is_match("Error"), true);
+        assert_eq!(reg.is_match("ERROR"), true);
+        let reg = RegexBuilder::new("[a-c]x[^k]")
+            .case_insensitive(true)
+            .build()
+            .context("编译模式串出错")?;
+        assert_eq!(reg.is_match("BXa"), true);
+        assert_eq!(reg.is_match("bxK"), false);
+        assert_eq!(reg.is_match("bx\u{212A}"), false);
+        let reg = RegexBuilder::new("ok")
+            .case_insensitive(true)
+            .build()
+            .context("编译模式串出错")?;
+        assert_eq!(reg.is_match("O\u{212A}"), true);
+        let reg = RegexBuilder::new("(?-i)a")
+            .case_insensitive(true)
+            .build()
+            .context("编译模式串出错")?;
+        assert_eq!(reg.is_match("A"), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_insensitive_backref() -> Result<(), Error> {
+        let reg = Regex::new(r"(?i)(\w+) \1").context("编译模式串出错")?;
+        assert_eq!(reg.find("Hello HELLO").unwrap().as_str(), "Hello HELLO");
+        let reg = Regex::new(r"(?i)(k)\1").context("编译模式串出错")?;
+        assert_eq!(reg.find("k\u{212A}!").unwrap().range(), 0..4);
+        let reg = Regex::new(r"(\w+) \1").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("Hello HELLO"), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_line_option() -> Result<(), Error> {
+        let reg = RegexBuilder::new(r"^\w+$")
+            .multi_line(true)
+            .build()
+            .context("编译模式串出错")?;
+        let text = "one\ntwo\nthree";
+        let lines: Vec<&str> = reg.find_iter(text).map(|m| m.as_str()).collect();
+        assert_eq!(lines, vec!["one", "two", "three"]);
+        let reg = Regex::new(r"^\w+$").context("编译模式串出错")?;
+        assert_eq!(reg.is_match(text), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_anchors() -> Result<(), Error> {
+        let reg = Regex::new(r"(?m)\Atwo").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("one\ntwo"), false);
+        assert_eq!(reg.is_match("two\none"), true);
+        let reg = Regex::new(r"(?m)one\z").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("one\ntwo"), false);
+        assert_eq!(reg.is_match("two\none\n"), false);
+        assert_eq!(reg.is_match("two\none"), true);
+        let reg = Regex::new(r"one\Z").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("two\none\n"), true);
+        assert_eq!(reg.is_match("one\n\n"), false);
+        assert_eq!(reg.find("one\n").unwrap().range(), 0..3);
+        let reg = Regex::new(r"(\w)\1\Z").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("xaa\n"), true);
+        let reg = Regex::new(r"\A(\w)\1").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("xaa"), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_any_literal() -> Result<(), Error> {
+        let reg = Regex::new(r"a/b=c").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("x a/b=c"), true);
+        let reg = Regex::new(r"café!").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("un café!"), true);
+        assert_eq!(reg.is_match("un cafe!"), false);
+        let reg = Regex::new(r"中文\d").context("编译模式串出错")?;
+        assert_eq!(reg.find("说中文3").unwrap().as_str(), "中文3");
+        let reg = Regex::new(r"a]b}").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("a]b}"), true);
+        assert!(Regex::new(r"*a").is_err());
+        assert!(Regex::new(r"a)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_escaped_metachars() -> Result<(), Error> {
+        let reg = Regex::new(r"\.\(\[\*\+\?\{\|\^\$\)").context("编译模式串出错")?;
+        assert_eq!(reg.is_match(".([*+?{|^$)"), true);
+        let reg = Regex::new(r"1\.5").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("125"), false);
+        assert_eq!(reg.is_match("1.5"), true);
+        let reg = Regex::new(r"[\]\-\\]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("a]-\\b").unwrap().as_str(), "]-\\");
+        assert!(Regex::new(r"\q").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_anchors_anywhere() -> Result<(), Error> {
+        let reg = Regex::new(r"(a$|b)").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("xa"), true);
+        assert_eq!(reg.is_match("ax"), false);
+        assert_eq!(reg.is_match("bx"), true);
+        let reg = Regex::new(r"(^a|b)c").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("ac"), true);
+        assert_eq!(reg.is_match("xac"), false);
+        assert_eq!(reg.is_match("xbc"), true);
+        let reg = Regex::new(r"a$b").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("ab"), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_anchored_backtrack_search() -> Result<(), Error> {
+        // 开头是 '^' 时，回溯的 VM 只从文本开头尝试一次
+        let text = "ab".repeat(50_000);
+        let reg = Regex::new(r"^(\w)\1").context("编译模式串出错")?;
+        let started = Instant::now();
+        assert!(reg.find(&text).is_none());
+        assert_eq!(reg.find_iter(&text).count(), 0);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(reg.find_iter("aaaa").count(), 1);
+        // 多行模式的 '^' 仍然可以在每一行开头匹配
+        let reg = Regex::new(r"(?m)^(\w)\1").context("编译模式串出错")?;
+        assert_eq!(reg.find("ab\ncc").unwrap().range(), 3..5);
+        let reg = RegexBuilder::new(r"\A(\w)\1")
+            .multi_line(true)
+            .build()
+            .context("编译模式串出错")?;
+        assert!(reg.find("ab\ncc").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_negated_and_space_escapes() -> Result<(), Error> {
+        let reg = Regex::new(r"\s\S+\s").context("编译模式串出错")?;
+        assert_eq!(reg.find("a\tbc d").unwrap().as_str(), "\tbc ");
+        let reg = Regex::new(r"\D\W").context("编译模式串出错")?;
+        assert_eq!(reg.find("1a2!b!").unwrap().as_str(), "b!");
+        let reg = Regex::new(r"[\D\s]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("12ab c3").unwrap().as_str(), "ab c");
+        let reg = Regex::new(r"[^\W\d]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("12_ab3").unwrap().as_str(), "_ab");
+        let reg = Regex::new(r"[\w]+").context("编译模式串出错")?;
+        assert_eq!(reg.find(" héllo ").unwrap().as_str(), "héllo");
+        Ok(())
+    }
+
+    #[test]
+    fn test_control_and_hex_escapes() -> Result<(), Error> {
+        let reg = Regex::new(r"a\tb\nc\r").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("a\tb\nc\r"), true);
+        let reg = Regex::new(r"\x41\x{42}\u{e9}\u{1F600}").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("ABé😀"), true);
+        let reg = Regex::new(r"[\x30\n]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("a\n\n0b").unwrap().as_str(), "\n\n0");
+        let reg = Regex::new(r"\cA\e\0").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("\x01\x1B\0"), true);
+        assert!(Regex::new(r"\x4").is_err());
+        assert!(Regex::new(r"\x{110000}").is_err());
+        assert!(Regex::new(r"\u{d800}").is_err());
+        assert!(Regex::new(r"\x{+1}").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unicode_property() -> Result<(), Error> {
+        let reg = Regex::new(r"\p{Han}+").context("编译模式串出错")?;
+        assert_eq!(reg.find("hello 世界!").unwrap().as_str(), "世界");
+        let reg = Regex::new(r"\p{Greek}\P{Greek}").context("编译模式串出错")?;
+        assert_eq!(reg.find("αβc").unwrap().as_str(), "βc");
+        let reg = Regex::new(r"\pL+\p{Nd}").context("编译模式串出错")?;
+        assert_eq!(reg.find("-- élan٣").unwrap().as_str(), "élan٣");
+        let reg = Regex::new(r"[\p{Lu}\d]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("abCΔ12e").unwrap().as_str(), "CΔ12");
+        let reg = Regex::new(r"[^\P{L}]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("12 ab3").unwrap().as_str(), "ab");
+        assert!(Regex::new(r"\p{Klingon}").is_err());
+        assert!(Regex::new(r"\p{L").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_posix_classes() -> Result<(), Error> {
+        let reg = Regex::new(r"[[:digit:]]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("ab123c").unwrap().as_str(), "123");
+        let reg = Regex::new(r"[[:upper:][:digit:]_]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("abC_9d").unwrap().as_str(), "C_9");
+        let reg = Regex::new(r"[^[:alpha:][:space:]]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("ab c1!2 d").unwrap().as_str(), "1!2");
+        let reg = Regex::new(r"[[:^punct:]]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("!!ab?").unwrap().as_str(), "ab");
+        let reg = Regex::new(r"[[:xdigit:]g-h]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("xyzfahg0z").unwrap().as_str(), "fahg0");
+        let reg = Regex::new(r"[[:blank:]][[:cntrl:]][[:graph:]][[:print:]]")
+            .context("编译模式串出错")?;
+        assert_eq!(reg.is_match("\t\x01~ "), true);
+        let reg = Regex::new(r"(?i)[[:upper:]]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("1abC2").unwrap().as_str(), "abC");
+        let reg = Regex::new(r"[[:lower:][:alnum:]]").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("é"), false);
+        assert!(Regex::new(r"[[:foo:]]").is_err());
+        assert!(Regex::new(r"[[:alpha]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unicode_ranges() -> Result<(), Error> {
+        let reg = Regex::new(r"[α-ω]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("abc λόγος").unwrap().as_str(), "λ");
+        let reg = Regex::new(r"[α-ωά-ώ]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("abc λόγος").unwrap().as_str(), "λόγος");
+        let reg = Regex::new(r"[\u{4e00}-\u{9fff}]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("hi 你好!").unwrap().as_str(), "你好");
+        let reg = Regex::new(r"[^a]").context("编译模式串出错")?;
+        assert_eq!(reg.find("aa😀").unwrap().as_str(), "😀");
+        let reg = Regex::new(r"[\x00-\x{10FFFF}]").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("\u{10FFFF}"), true);
+        let reg = Regex::new(r"[+-\-]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("a+,-b").unwrap().as_str(), "+,-");
+        assert!(Regex::new(r"[z-a]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_insensitive_classes() -> Result<(), Error> {
+        let reg = Regex::new(r"(?i)[^a]").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("A"), false);
+        assert_eq!(reg.is_match("b"), true);
+        let reg = Regex::new(r"(?i)[α-γ]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("ΑΒΓΔ").unwrap().as_str(), "ΑΒΓ");
+        let reg = Regex::new(r"(?i)\P{Lu}").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("aB"), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_class_set_operations() -> Result<(), Error> {
+        let reg = Regex::new(r"[a-z&&[^aeiou]]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("queue strength").unwrap().as_str(), "q");
+        assert_eq!(reg.find("aei strength").unwrap().as_str(), "str");
+        let reg = Regex::new(r"[\w--\d]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("12ab_3").unwrap().as_str(), "ab_");
+        let reg = Regex::new(r"[a-f~~d-z]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("def abcdx").unwrap().as_str(), "abc");
+        let reg = Regex::new(r"[\p{Greek}&&\p{Ll}]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("ΑΒγδ").unwrap().as_str(), "γδ");
+        let reg = Regex::new(r"[^a-z&&[^aeiou]]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("xyzaei").unwrap().as_str(), "aei");
+        assert_eq!(reg.is_match("b"), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_classes() -> Result<(), Error> {
+        let reg = Regex::new(r"[a[0-9][^\w]]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("ba1!b").unwrap().as_str(), "a1!");
+        let reg = Regex::new(r"[[a-c][x-z]--[by]]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("bacxzy").unwrap().as_str(), "acxz");
+        let reg = Regex::new(r"(?i)[[a-c]&&[^b]]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("BACb").unwrap().as_str(), "AC");
+        let reg = Regex::new(r"[a&b~c-]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("x&~-b").unwrap().as_str(), "&~-b");
+        assert!(Regex::new(r"[a[b]").is_err());
+        // 紧跟在 '[' 或 '[^' 后面的 ']' 是普通字符
+        let reg = Regex::new(r"[]a]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("x]a]b").unwrap().as_str(), "]a]");
+        let reg = Regex::new(r"[^]a]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("]ab]").unwrap().as_str(), "b");
+        let reg = Regex::new(r"[]-a]").context("编译模式串出错")?;
+        assert_eq!(reg.find("_").unwrap().as_str(), "_");
+        let reg = Regex::new(r"[a[]b]]+").context("编译模式串出错")?;
+        assert_eq!(reg.find("x]ab]").unwrap().as_str(), "]ab]");
+        assert!(Regex::new(r"[]").is_err());
+        assert!(Regex::new(r"[^]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_level_alternation() -> Result<(), Error> {
+        let reg = Regex::new(r"cat|dog").context("编译模式串出错")?;
+        assert_eq!(reg.find("hotdog cat").unwrap().as_str(), "dog");
+        assert_eq!(reg.is_match("cow"), false);
+        let reg = Regex::new(r"^a|b$").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("ax"), true);
+        assert_eq!(reg.is_match("xb"), true);
+        assert_eq!(reg.is_match("xax"), false);
+        let reg = Regex::new(r"a|ab|abc").context("编译模式串出错")?;
+        assert_eq!(reg.find("abc").unwrap().as_str(), "a");
+        let reg = Regex::new(r"x(a|b)+y|z").context("编译模式串出错")?;
+        assert_eq!(reg.find("xabay").unwrap().as_str(), "xabay");
+        assert!(Regex::new(r"a)|b").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_alternatives() -> Result<(), Error> {
+        let reg = Regex::new(r"x(a|)y").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("xy"), true);
+        assert_eq!(reg.is_match("xay"), true);
+        let reg = Regex::new(r"x(|a)").context("编译模式串出错")?;
+        assert_eq!(reg.find("xa").unwrap().as_str(), "x");
+        let reg = Regex::new(r"x(a|)").context("编译模式串出错")?;
+        assert_eq!(reg.find("xa").unwrap().as_str(), "xa");
+        let reg = Regex::new(r"|b").context("编译模式串出错")?;
+        assert_eq!(reg.find("b").unwrap().range(), 0..0);
+        let reg = Regex::new(r"x(a||b)y").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("xy"), true);
+        assert_eq!(reg.is_match("xby"), true);
+        let reg = Regex::new(r"(|a)\1b").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("b"), true);
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_counted_repetition() -> Result<(), Error> {
+        // 展开后放得下就照样展开，is_match 先走 DFA
+        let reg = Regex::new(r"^(abc){1000,5000}$").context("编译模式串出错")?;
+        assert_eq!(Dfa::supports(&reg.instrs), true);
+        assert_eq!(reg.is_match(&"abc".repeat(1000)), true);
+        assert_eq!(reg.is_match(&"abc".repeat(999)), false);
+        assert_eq!(reg.is_match(&"abc".repeat(5001)), false);
+        let text = "abc".repeat(1200);
+        let caps = reg.captures(&text).unwrap();
+        assert_eq!(caps.get(1).unwrap().range(), 3597..3600);
+        let reg = Regex::new(r"\d{1,100}x").context("编译模式串出错")?;
+        assert_eq!(Dfa::supports(&reg.instrs), true);
+        assert_eq!(reg.is_match(&format!("{}x", "1".repeat(150))), true);
+
+        // 展开后超出上限时用计数器循环，只有一份循环体，DFA 执行不了就交给 Pike VM
+        let reg = Regex::new(r"^(abc){1000,500000}$").context("编译模式串出错")?;
+        assert!(reg.instrs.len() < 20);
+        assert_eq!(PikeVM::supports(&reg.instrs), true);
+        assert_eq!(Dfa::supports(&reg.instrs), false);
+        assert_eq!(reg.is_match(&"abc".repeat(1000)), true);
+        assert_eq!(reg.is_match(&"abc".repeat(999)), false);
+        let text = "abc".repeat(1200);
+        let caps = reg.captures(&text).unwrap();
+        assert_eq!(caps.get(1).unwrap().range(), 3597..3600);
+
+        // 有反向引用时交给回溯的 VM
+        let reg = Regex::new(r"^(abc){1000,5000}\1$").context("编译模式串出错")?;
+        assert!(reg.instrs.len() < 20);
+        assert!(reg.is_match(&"abc".repeat(1001)));
+        assert!(!reg.is_match(&"abc".repeat(1000)));
+        let text = "abc".repeat(1201);
+        let caps = reg.captures(&text).unwrap();
+        assert_eq!(caps.get(1).unwrap().range(), 3597..3600);
+
+        let reg = Regex::new(r"a{200,300}?").context("编译模式串出错")?;
+        assert_eq!(reg.find(&"a".repeat(400)).unwrap().len(), 200);
+        let reg = Regex::new(r"a{200,}b").context("编译模式串出错")?;
+        assert_eq!(
+            reg.find(&format!("{}b", "a".repeat(250))).unwrap().len(),
+            251
+        );
+        assert_eq!(reg.is_match(&format!("{}b", "a".repeat(199))), false);
+        let reg = Regex::new(r"^((a{10}){10}){10}$").context("编译模式串出错")?;
+        assert_eq!(reg.is_match(&"a".repeat(1000)), true);
+        assert_eq!(reg.is_match(&"a".repeat(999)), false);
+        let reg = Regex::new(r"x{2,1000}+x").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("xxxx"), false);
+        // 可以不消耗字符的循环体不会真的转十亿圈
+        let reg = Regex::new(r"^(?:a?){0,1000000000}(?!.)").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("aaa"), true);
+        let reg = Regex::new(r"^(?:a?){1000000000}(?!.)").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("aaa"), true);
+        // 展开的可选块是嵌套的，能匹配空串也不会指数回溯
+        let reg = Regex::new(r"(?:\b){0,100}?(a)\1x").context("编译模式串出错")?;
+        assert_eq!(reg.is_match("aaaa"), false);
+        let reg = Regex::new(r"^(?:){1000000000}$").context("编译模式串出错")?;
+        assert_eq!(reg.is_match(""), true);
+        // 外层每转一圈都从头进入内层的计数器循环
+        let reg = Regex::new(r"^(a)\1(?:(?:a{3}){50}){2,1000}$").context("编译模式串出错")?;
+        assert_eq!(reg.is_match(&"a".repeat(302)), true);
+        assert_eq!(reg.is_match(&"a".repeat(303)), false);
+        // Pike VM 里同样在空的一圈之后离开循环
+        let reg = Regex::new(r"^(?:a?){1000000000}$").context("编译模式串出错")?;
+        assert_eq!(PikeVM::supports(&reg.instrs), true);
+        assert_eq!(reg.is_match("aaa"), true);
+        let reg = Regex::new(r"^(?:(?:a{3}){50}){2,1000}$").context("编译模式串出错")?;
+        assert_eq!(reg.is_match(&"a".repeat(450)), true);
+        assert_eq!(reg.is_match(&"a".repeat(451)), false);
+        assert_eq!(reg.is_match(&"a".repeat(150)), false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_counted_repetition_linear_time() -> Result<(), Error> {
+        // 没有反向引用的大量词交给 DFA 和 Pike VM，带着计数器也不会指数回溯
+        let text = "a".repeat(2000);
+        for pattern in [
+            r"(?:a|a){0,200}b",
+            r"(?:a*){0,100}b",
+            r"(?:(?:a|a){0,20}){0,50}b",
+            r"(?:a|a){0,600000}b",
+            r"(?:a?){0,600000}b",
+            r"(?:a|aa){1000,600000}b",
+        ] {
+            let reg = Regex::new(pattern).context("编译模式串出错")?;
+            let started = Instant::now();
+            assert!(!reg.is_match(&text));
+            assert!(reg.find(&text).is_none());
+            assert!(started.elapsed() < Duration::from_secs(5), "{}", pattern);
+        }
+        // 计数器在 Pike VM 里每个值都是单独的状态，嵌套时状态数是圈数的乘积，展开后又放不下，报超出上限
+        for pattern in [
+            r"(?:(?:a|b){0,1000}){0,1000}c",
+            r"(?:(?:a|b){1,1000}){0,1000}c",
+            r"((a{10}){100}){1000}",
+            r"(?:a{0,2000000}){0,2000000}",
+        ] {
+            let err = Regex::new(pattern).err().context("应该超过大小上限")?;
+            let err = err
+                .downcast_ref::<ParseError>()
+                .context("应该是 ParseError")?;
+            assert_eq!(err.kind, ErrorKind::ProgramTooBig(DEFAULT_SIZE_LIMIT));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_size_limit() -> Result<(), Error> {
+        let err = RegexBuilder::new(r"\w{100}x{100}")
+            .size_limit(150)
+            .build()
+            .err()
+            .context("应该超过大小上限")?;
+        let err = err
+            .downcast_ref::<ParseError>()
+            .context("应该是 ParseError")?;
+        assert_eq!(err.kind, ErrorKind::ProgramTooBig(150));
+        assert_eq!(err.code(), "size_limit_exceeded");
+        let reg = RegexBuilder::new(r"\w{100}x{100}")
+            .size_limit(300)
+            .build()?;
+        assert_eq!(reg.is_match(&"x".repeat(200)), true);
+        // 大字符类的每个区间都要算进大小，展开一百万份 \p{L} 要占好几 GB
+        let err = Regex::new(r"(?:\p{L}{1000}){1000}")
+            .err()
+            .context("应该超过大小上限")?;
+        let err = err
+            .downcast_ref::<ParseError>()
+            .context("应该是 ParseError")?;
+        assert_eq!(err.kind, ErrorKind::ProgramTooBig(DEFAULT_SIZE_LIMIT));
+        // 放不下时改用计数器循环，字符类只有一份
+        let reg = Regex::new(r"^(?:\p{L}?){0,300000}$")?;
+        assert!(reg.instrs.len() < 10);
+        assert_eq!(reg.is_match(&"é".repeat(1000)), true);
+        assert_eq!(reg.is_match("é1"), false);
+        Ok(())
+    }
+
+    /// 同一个程序分别交给 Pike VM 和回溯的 VM，两边的匹配和捕获位置要相同
+    fn both_engines(pattern: &str, text: &str) -> Result<Vec<Option<usize>>, Error> {
+        let reg = Regex::new(pattern).context("编译模式串出错")?;
+        assert!(PikeVM::supports(&reg.instrs), "{}", pattern);
+        let input = Text::new(text);
+        let mut pike = vec![None; reg.captures_len() * 2];
+        let mut backtrack = pike.clone();
+        PikeVM::new(&reg.instrs).search(&input, 0, &mut pike);
+        reg.search_backtrack(&input, 0, &mut backtrack);
+        assert_eq!(pike, backtrack, "{}", pattern);
+        Ok(pike)
+    }
+
+    #[test]
+    fn test_empty_iteration_leaves_loop() -> Result<(), Error> {
+        // 某一圈没有消耗字符时离开循环，从循环后面继续
+        let slots = both_engines(r"Σ.(\b|\p{Lu})+", "Σ-K_ bΣ")?;
+        assert_eq!(slots[..2], [Some(0), Some(3)]);
+        let slots = both_engines(r"([a-c]{0,2}|\p{Lu})+", "A1")?;
+        assert_eq!(slots[..2], [Some(0), Some(0)]);
+        let slots = both_engines(r"(\B)*", "")?;
+        assert_eq!(slots, [Some(0), Some(0), Some(0), Some(0)]);
+        let slots = both_engines(r"(c?[ac]?){2,}", "aabc")?;
+        assert_eq!(slots, [Some(0), Some(2), Some(2), Some(2)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_counted_loop_engines() -> Result<(), Error> {
+        // 计数器循环在两个引擎里的优先级和捕获位置相同
+        let text = format!("{}c", "ab".repeat(150));
+        let slots = both_engines(r"(a|ab){2,200}c", &text)?;
+        assert_eq!(slots, [Some(0), Some(301), Some(298), Some(300)]);
+        let slots = both_engines(r"(a|ab){100,200}?b", &text)?;
+        assert_eq!(slots, [Some(0), Some(200), Some(198), Some(199)]);
+        both_engines(r"((?:a|b){2,3}){100,200}c", &text)?;
+        both_engines(r"(?:(a?)b){150,}c", &text)?;
+        both_engines(r"(?:x|(a)|(b)){0,160}?c", &text)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_loop_priority() -> Result<(), Error> {
+        // 嵌套的非贪婪循环和可以为空的循环，两个引擎的优先级相同
+        let slots = both_engines(r"(a*?)*", "aa")?;
+        assert_eq!(slots, [Some(0), Some(0), Some(0), Some(0)]);
+        let slots = both_engines(r"c(?:c*?)*", "cc")?;
+        assert_eq!(slots, [Some(0), Some(1)]);
+        let slots = both_engines(r"(?:a*?)*?b", "aab")?;
+        assert_eq!(slots, [Some(0), Some(3)]);
+        let slots = both_engines(r"(a|)*?b", "aab")?;
+        assert_eq!(slots, [Some(0), Some(3), Some(1), Some(2)]);
+        let slots = both_engines(r"((a)*?|b)*", "ab")?;
+        assert_eq!(slots, [Some(0), Some(0), Some(0), Some(0), None, None]);
+        let slots = both_engines(r"((?:a|)*?)+x", "aax")?;
+        assert_eq!(slots, [Some(0), Some(3), Some(0), Some(2)]);
+        both_engines(r"(?:(a)|b|)*?c", "abc")?;
+        both_engines(r"(?:a??)+?b", "aab")?;
+        // 加上只能回溯执行的结构，结果也不变
+        let reg = Regex::new(r"(?:(a*?)*)(?=)").context("编译模式串出错")?;
+        assert_eq!(reg.find("aa").unwrap().range(), 0..0);
+        let reg = Regex::new(r"c(?:c*?)*(?=)").context("编译模式串出错")?;
+        assert_eq!(reg.find("cc").unwrap().range(), 0..1);
+        Ok(())
+    }
 }
@@ -0,0 +1,248 @@
+use crate::regex::parser::Parser;
+use std::ops::Range;
+
+pub use crate::regex::{
+    class::ClassSet,
+    parser::{ErrorKind, ParseError},
+};
+
+/// 正则表达式的语法树。`Parser` 生成它，`Compiler` 再把它翻译成 `Inst` 程序。
+/// 内联标志在解析时就已经生效，树里不再出现 `(?i)` 这样的节点
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ast {
+    Empty,
+    // 忽略大小写时匹配所有大小写等价的字符
+    Literal { ch: char, ignore_case: bool },
+    // '.'，(?s) 下也匹配 '\n'
+    Dot { newline: bool },
+    Digit, // \d
+    Word,  // \w
+    Class(ClassSet),
+    Assertion(Assertion),
+    // \1、\k<name>
+    BackRef { group: usize, ignore_case: bool },
+    Repetition(Repetition),
+    Group(Group),
+    Concat(Vec<Ast>),
+    Alternation(Vec<Ast>),
+}
+
+/// 不消耗字符的断言
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assertion {
+    Start,            // '^'、\A
+    End,              // '$'、\z
+    StartLine,        // (?m) 下的 '^'
+    EndLine,          // (?m) 下的 '$'
+    EndBeforeNewline, // \Z
+    WordBoundary,     // \b
+    NotWordBoundary,  // \B
+}
+
+/// `*`、`+`、`?`、`{n,m}`，max 为 None 表示没有上限。
+/// span 是被重复的部分连同量词在模式串里的字节范围，编译出错时用来标出位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repetition {
+    pub ast: Box<Ast>,
+    pub min: usize,
+    pub max: Option<usize>,
+    pub greed: Greed,
+    pub span: Range<usize>,
+}
+
+/// 量词后缀：默认贪婪，'?' 是非贪婪（优先少匹配），'+' 是占有（匹配完不再回溯）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Greed {
+    Greedy,
+    Lazy,
+    Possessive,
+}
+
+/// span 是从 '(' 到 ')' 的字节范围
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub kind: GroupKind,
+    pub ast: Box<Ast>,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupKind {
+    // (...)、(?P<name>...)、(?<name>...)，index 是组号
+    Capture { index: usize, name: Option<String> },
+    // (?:...)、(?i:...)
+    NonCapture,
+    // (?>...)
+    Atomic,
+    // (?=...)、(?!...)
+    LookAhead { negate: bool },
+    // (?<=...)、(?<!...)
+    LookBehind { negate: bool },
+}
+
+impl Ast {
+    /// 能匹配的最短和最长字符数，没有上限时返回 None
+    pub fn length_bounds(&self) -> Option<(usize, usize)> {
+        match self {
+            Ast::Empty | Ast::Assertion(_) => Some((0, 0)),
+            Ast::Literal { .. } | Ast::Dot { .. } | Ast::Digit | Ast::Word | Ast::Class(_) => {
+                Some((1, 1))
+            }
+            // 引用的内容在匹配时才知道
+            Ast::BackRef { .. } => None,
+            Ast::Repetition(rep) => {
+                let (min, max) = rep.ast.length_bounds()?;
+                Some((min.saturating_mul(rep.min), max.checked_mul(rep.max?)?))
+            }
+            Ast::Group(group) => match group.kind {
+                // 环视不占长度
+                GroupKind::LookAhead { .. } | GroupKind::LookBehind { .. } => Some((0, 0)),
+                _ => group.ast.length_bounds(),
+            },
+            Ast::Concat(asts) => asts.iter().try_fold((0usize, 0usize), |(min, max), ast| {
+                let (lo, hi) = ast.length_bounds()?;
+                Some((min.saturating_add(lo), max.checked_add(hi)?))
+            }),
+            Ast::Alternation(asts) => {
+                let mut bounds = asts.iter().map(Ast::length_bounds);
+                let first = bounds.next()??;
+                bounds.try_fold(first, |(min, max), bound| {
+                    let (lo, hi) = bound?;
+                    Some((min.min(lo), max.max(hi)))
+                })
+            }
+        }
+    }
+}
+
+impl Ast {
+    /// 是否含有只能用回溯的 VM 执行的结构：反向引用、原子分组、占有量词和环视
+    pub fn needs_backtrack(&self) -> bool {
+        match self {
+            Ast::BackRef { .. } => true,
+            Ast::Repetition(rep) => rep.greed == Greed::Possessive || rep.ast.needs_backtrack(),
+            Ast::Group(group) => match group.kind {
+                GroupKind::Capture { .. } | GroupKind::NonCapture => group.ast.needs_backtrack(),
+                _ => true,
+            },
+            Ast::Concat(asts) | Ast::Alternation(asts) => asts.iter().any(Ast::needs_backtrack),
+            _ => false,
+        }
+    }
+}
+
+impl Ast {
+    /// 按名字的反向引用在解析时记作第 0 组，解析完再按出现的顺序换成 groups 里的组号
+    pub(crate) fn resolve_named_refs(&mut self, groups: &mut impl Iterator<Item = usize>) {
+        match self {
+            Ast::BackRef {
+                group: group @ 0, ..
+            } => *group = groups.next().unwrap_or_default(),
+            Ast::Repetition(rep) => rep.ast.resolve_named_refs(groups),
+            Ast::Group(group) => group.ast.resolve_named_refs(groups),
+            Ast::Concat(asts) | Ast::Alternation(asts) => {
+                for ast in asts {
+                    ast.resolve_named_refs(groups);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 解析模式串，返回语法树
+pub fn parse(pattern: &str) -> Result<Ast, ParseError> {
+    Parser::new(pattern).parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regex::ast::*;
+
+    #[test]
+    fn test_parse() {
+        let ast = parse(r"a|(?<x>b)*?").unwrap();
+        assert_eq!(
+            ast,
+            Ast::Alternation(vec![
+                Ast::Literal {
+                    ch: 'a',
+                    ignore_case: false
+                },
+                Ast::Repetition(Repetition {
+                    ast: Box::new(Ast::Group(Group {
+                        kind: GroupKind::Capture {
+                            index: 1,
+                            name: Some("x".to_string())
+                        },
+                        ast: Box::new(Ast::Literal {
+                            ch: 'b',
+                            ignore_case: false
+                        }),
+                        span: 2..9,
+                    })),
+                    min: 0,
+                    max: None,
+                    greed: Greed::Lazy,
+                    span: 2..11,
+                }),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        let ast = parse(r"(?i)a(?-i:\b.)").unwrap();
+        assert_eq!(
+            ast,
+            Ast::Concat(vec![
+                Ast::Literal {
+                    ch: 'a',
+                    ignore_case: true
+                },
+                Ast::Group(Group {
+                    kind: GroupKind::NonCapture,
+                    ast: Box::new(Ast::Concat(vec![
+                        Ast::Assertion(Assertion::WordBoundary),
+                        Ast::Dot { newline: false },
+                    ])),
+                    span: 5..14,
+                }),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_class() {
+        let ranges = |pattern: &str| match parse(pattern).unwrap() {
+            Ast::Class(set) => set.ranges().to_vec(),
+            ast => panic!("不是字符类：{ast:?}"),
+        };
+        assert_eq!(ranges(r"[c-ea-b]"), vec![('a', 'e')]);
+        assert_eq!(ranges(r"[^b-y]"), vec![('\0', 'a'), ('z', char::MAX)]);
+        assert_eq!(ranges(r"[\d_]"), vec![('0', '9'), ('_', '_')]);
+        assert_eq!(
+            ranges(r"(?i)[k]"),
+            vec![('K', 'K'), ('k', 'k'), ('\u{212A}', '\u{212A}')]
+        );
+    }
+
+    #[test]
+    fn test_length_bounds() {
+        let bounds = |pattern: &str| parse(pattern).unwrap().length_bounds();
+        assert_eq!(bounds(r"ab|c{2,4}"), Some((2, 4)));
+        assert_eq!(bounds(r"a(?=bcd)\b"), Some((1, 1)));
+        assert_eq!(bounds(r"a+"), None);
+        assert_eq!(bounds(r"(a)\1"), None);
+    }
+
+    #[test]
+    fn test_needs_backtrack() {
+        let needs = |pattern: &str| parse(pattern).unwrap().needs_backtrack();
+        assert!(!needs(r"(a|b)*?(?:c{2,5})\b"));
+        assert!(needs(r"x|(a)\1"));
+        assert!(needs(r"(?:a(?=b))+"));
+        assert!(needs(r"a*+"));
+        assert!(needs(r"(?>a)"));
+    }
+}
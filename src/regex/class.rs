@@ -0,0 +1,284 @@
+use crate::regex::{fold, unicode};
+
+/// 字符类：按码点升序排列、互不重叠也不相邻的闭区间，判断成员时二分查找。
+/// 取反、并集等操作都直接在区间上做，`[^a]` 这种类也只需要两个区间
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassSet {
+    ranges: Vec<(char, char)>,
+}
+
+/// 代理区的码点不是合法的 `char`，'\u{D7FF}' 的下一个字符是 '\u{E000}'
+fn succ(ch: char) -> Option<char> {
+    match ch {
+        '\u{D7FF}' => Some('\u{E000}'),
+        _ => char::from_u32(ch as u32 + 1),
+    }
+}
+
+fn pred(ch: char) -> Option<char> {
+    match ch {
+        '\u{E000}' => Some('\u{D7FF}'),
+        _ => char::from_u32((ch as u32).checked_sub(1)?),
+    }
+}
+
+impl ClassSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_ranges(ranges: &[(char, char)]) -> Self {
+        let mut set = Self::new();
+        for &(lo, hi) in ranges {
+            set.push(lo, hi);
+        }
+        set
+    }
+
+    /// 加入 `[lo, hi]`，lo 不能大于 hi。
+    /// 和它重叠或相邻的区间是连续的一段，二分查找出这一段，合并成一个区间
+    pub fn push(&mut self, lo: char, hi: char) {
+        debug_assert!(lo <= hi);
+        let start = self
+            .ranges
+            .partition_point(|&(_, end)| succ(end).is_some_and(|next| next < lo));
+        let end = self
+            .ranges
+            .partition_point(|&(start, _)| pred(start).map_or(true, |prev| prev <= hi));
+        if start == end {
+            self.ranges.insert(start, (lo, hi));
+            return;
+        }
+        let merged = (lo.min(self.ranges[start].0), hi.max(self.ranges[end - 1].1));
+        self.ranges.splice(start..end, [merged]);
+    }
+
+    pub fn push_char(&mut self, ch: char) {
+        self.push(ch, ch);
+    }
+
+    pub fn union(&mut self, other: &ClassSet) {
+        self.extend(other.ranges.iter().copied());
+    }
+
+    /// 交集，两边都是有序的区间，一起往后扫一遍
+    pub fn intersect(&mut self, other: &ClassSet) {
+        let mut result = vec![];
+        let (mut i, mut j) = (0, 0);
+        while let (Some(&(lo1, hi1)), Some(&(lo2, hi2))) = (self.ranges.get(i), other.ranges.get(j))
+        {
+            let (lo, hi) = (lo1.max(lo2), hi1.min(hi2));
+            if lo <= hi {
+                result.push((lo, hi));
+            }
+            // 先结束的区间不会再和后面的相交
+            if hi1 < hi2 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        self.ranges = result;
+    }
+
+    /// 差集：在 self 中但不在 other 中
+    pub fn difference(&mut self, other: &ClassSet) {
+        let mut negated = other.clone();
+        negated.negate();
+        self.intersect(&negated);
+    }
+
+    /// 对称差：只在其中一边出现
+    pub fn symmetric_difference(&mut self, other: &ClassSet) {
+        let mut both = self.clone();
+        both.intersect(other);
+        self.union(other);
+        self.difference(&both);
+    }
+
+    /// 取补集
+    pub fn negate(&mut self) {
+        let mut negated = Vec::with_capacity(self.ranges.len() + 1);
+        let mut next = Some('\0');
+        for &(lo, hi) in self.ranges.iter() {
+            if let Some(start) = next {
+                if let Some(end) = pred(lo).filter(|end| start <= *end) {
+                    negated.push((start, end));
+                }
+            }
+            next = succ(hi);
+        }
+        if let Some(start) = next {
+            negated.push((start, char::MAX));
+        }
+        self.ranges = negated;
+    }
+
+    /// 加入所有成员的大小写等价字符。只需要遍历有大小写的字符，不用展开区间
+    pub fn case_fold(&mut self) {
+        let mut extra = vec![];
+        for (&ch, orbit) in fold::orbits() {
+            if self.contains(ch) {
+                extra.extend(orbit.iter().copied().filter(|c| !self.contains(*c)));
+            }
+        }
+        self.extend(extra.into_iter().map(|ch| (ch, ch)));
+    }
+
+    /// `\d` `\D` `\w` `\W` `\s` `\S` 对应的字符类
+    pub fn from_escape(ch: char) -> Option<Self> {
+        let (ranges, negated) = match ch {
+            'd' | 'D' => (&[('0', '9')][..], ch == 'D'),
+            'w' | 'W' => (unicode::PERL_WORD, ch == 'W'),
+            's' | 'S' => (unicode::WHITE_SPACE, ch == 'S'),
+            _ => return None,
+        };
+        let mut set = Self::from_ranges(ranges);
+        if negated {
+            set.negate();
+        }
+        Some(set)
+    }
+
+    /// POSIX 括号表达式里的字符类，和 GNU grep 在 C locale 下一样只包含 ASCII 字符
+    pub fn posix(name: &str) -> Option<Self> {
+        let ranges: &[(char, char)] = match name {
+            "alnum" => &[('0', '9'), ('A', 'Z'), ('a', 'z')],
+            "alpha" => &[('A', 'Z'), ('a', 'z')],
+            "blank" => &[('\t', '\t'), (' ', ' ')],
+            "cntrl" => &[('\0', '\x1F'), ('\x7F', '\x7F')],
+            "digit" => &[('0', '9')],
+            "graph" => &[('!', '~')],
+            "lower" => &[('a', 'z')],
+            "print" => &[(' ', '~')],
+            "punct" => &[('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+            "space" => &[('\t', '\r'), (' ', ' ')],
+            "upper" => &[('A', 'Z')],
+            "xdigit" => &[('0', '9'), ('A', 'F'), ('a', 'f')],
+            _ => return None,
+        };
+        Some(Self::from_ranges(ranges))
+    }
+
+    /// 按码点升序排列、互不重叠也不相邻的闭区间
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    pub fn contains(&self, ch: char) -> bool {
+        unicode::contains(&self.ranges, ch)
+    }
+
+    /// 一次加入很多区间，全部追加之后只整理一次，不用每个都去插入
+    fn extend(&mut self, ranges: impl IntoIterator<Item = (char, char)>) {
+        self.ranges.extend(ranges);
+        self.canonicalize();
+    }
+
+    /// 排序并合并重叠或相邻的区间
+    fn canonicalize(&mut self) {
+        self.ranges.sort_unstable();
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(self.ranges.len());
+        for &(lo, hi) in self.ranges.iter() {
+            match merged.last_mut() {
+                Some(last) if succ(last.1).map_or(true, |next| lo <= next) => {
+                    last.1 = last.1.max(hi)
+                }
+                _ => merged.push((lo, hi)),
+            }
+        }
+        self.ranges = merged;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regex::class::*;
+
+    #[test]
+    fn test_push_and_merge() {
+        let mut set = ClassSet::new();
+        set.push('m', 'p');
+        set.push('a', 'c');
+        set.push('d', 'f');
+        set.push('o', 'z');
+        set.push_char('\u{D7FF}');
+        set.push_char('\u{E000}');
+        assert_eq!(
+            set.ranges,
+            [('a', 'f'), ('m', 'z'), ('\u{D7FF}', '\u{E000}')]
+        );
+        assert!(set.contains('e'));
+        assert!(!set.contains('g'));
+        // 一次合并好几个区间，以及紧挨着两边的区间
+        set.push('e', 'n');
+        assert_eq!(set.ranges, [('a', 'z'), ('\u{D7FF}', '\u{E000}')]);
+        set.push('\u{E001}', char::MAX);
+        set.push('\0', '`');
+        assert_eq!(set.ranges, [('\0', 'z'), ('\u{D7FF}', char::MAX)]);
+    }
+
+    #[test]
+    fn test_push_out_of_order() {
+        // 倒序加入大量区间，每次都插到最前面
+        let mut set = ClassSet::new();
+        for code in (0..25_000u32).rev() {
+            set.push_char(char::from_u32(code * 2).unwrap());
+        }
+        assert_eq!(set.ranges.len(), 25_000);
+        assert!(set.contains('\u{1F00}') && !set.contains('\u{1F01}'));
+        let mut odd = ClassSet::new();
+        for code in (1..50_000u32).step_by(2) {
+            odd.push_char(char::from_u32(code).unwrap());
+        }
+        set.union(&odd);
+        assert_eq!(set.ranges, [('\0', char::from_u32(49_999).unwrap())]);
+    }
+
+    #[test]
+    fn test_negate() {
+        let mut set = ClassSet::from_ranges(&[('\0', 'a'), ('c', 'c')]);
+        set.negate();
+        assert_eq!(set.ranges, [('b', 'b'), ('d', char::MAX)]);
+        set.negate();
+        assert_eq!(set.ranges, [('\0', 'a'), ('c', 'c')]);
+
+        let mut empty = ClassSet::new();
+        empty.negate();
+        assert_eq!(empty.ranges, [('\0', char::MAX)]);
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let a = ClassSet::from_ranges(&[('a', 'm'), ('x', 'z')]);
+        let b = ClassSet::from_ranges(&[('f', 'y')]);
+
+        let mut set = a.clone();
+        set.intersect(&b);
+        assert_eq!(set.ranges, [('f', 'm'), ('x', 'y')]);
+
+        let mut set = a.clone();
+        set.difference(&b);
+        assert_eq!(set.ranges, [('a', 'e'), ('z', 'z')]);
+
+        let mut set = a.clone();
+        set.symmetric_difference(&b);
+        assert_eq!(set.ranges, [('a', 'e'), ('n', 'w'), ('z', 'z')]);
+    }
+
+    #[test]
+    fn test_case_fold() {
+        let mut set = ClassSet::from_ranges(&[('a', 'c'), ('k', 'k')]);
+        set.case_fold();
+        assert_eq!(
+            set.ranges,
+            [
+                ('A', 'C'),
+                ('K', 'K'),
+                ('a', 'c'),
+                ('k', 'k'),
+                ('\u{212A}', '\u{212A}')
+            ]
+        );
+    }
+}
@@ -0,0 +1,490 @@
+use crate::regex::{
+    ast::{Assertion, Ast, Greed, Group, GroupKind, Repetition},
+    class::ClassSet,
+    fold,
+    ir::{Inst, Program},
+    parser::{ErrorKind, ParseError},
+};
+use std::ops::Range;
+
+/// 编译后的程序默认的大小上限，大小的算法见 `Compiler::size`
+pub const DEFAULT_SIZE_LIMIT: usize = 1 << 20;
+
+/// 程序里已经有只能回溯执行的结构时，`{n,m}` 展开后的大小超过这么多就改用计数器循环。
+/// 否则只要不超过 size_limit 就照样展开，让 DFA 能执行，展开后放不下时才用计数器循环
+const EXPAND_LIMIT: usize = 128;
+
+/// 把语法树翻译成 `Inst` 程序。每个节点生成一段独立的代码，跳转都用相对偏移，
+/// 所以各段代码可以直接拼接
+pub struct Compiler<'p> {
+    pattern: &'p str,
+    // 下标是组号，第 0 组是整个匹配，没有名字
+    group_names: Vec<Option<String>>,
+    // 已经分配的计数器个数
+    counters: usize,
+    size_limit: usize,
+    // 整个程序反正只能交给回溯的 VM，大的 {n,m} 才能用计数器循环
+    backtrack: bool,
+    // 正在编译的量词和分组在模式串里的范围，由外到内
+    spans: Vec<Range<usize>>,
+}
+
+impl<'p> Compiler<'p> {
+    /// ast 是从 pattern 解析出来的，程序大小超过 size_limit 时返回错误
+    pub fn compile(ast: &Ast, pattern: &'p str, size_limit: usize) -> Result<Program, ParseError> {
+        let mut compiler = Self {
+            pattern,
+            group_names: vec![None],
+            counters: 0,
+            size_limit,
+            backtrack: ast.needs_backtrack(),
+            spans: vec![],
+        };
+        let mut instrs = compiler.compile_ast(ast)?;
+        instrs.push(Inst::Match);
+        compiler.check_size(&instrs)?;
+        Ok(Program {
+            instrs,
+            group_names: compiler.group_names,
+        })
+    }
+
+    /// 错误标在最里层正在编译的量词或分组上，不在任何量词或分组里时标出整个模式串
+    fn error(&self, kind: ErrorKind) -> ParseError {
+        let span = self.spans.last().cloned();
+        ParseError::new(kind, span.unwrap_or(0..self.pattern.len()), self.pattern)
+    }
+
+    /// 每条指令算 1，字符类再加上区间个数。展开量词时字符类也跟着复制，
+    /// `\p{L}` 这样几百个区间的类只按指令数算会严重低估占用的内存
+    fn size(code: &[Inst]) -> usize {
+        code.iter()
+            .map(|inst| match inst {
+                Inst::CharClass(set) => 1 + set.ranges().len(),
+                _ => 1,
+            })
+            .sum()
+    }
+
+    fn check_size(&self, code: &[Inst]) -> Result<(), ParseError> {
+        self.check_limit(Self::size(code))
+    }
+
+    fn check_limit(&self, size: usize) -> Result<(), ParseError> {
+        if size > self.size_limit {
+            return Err(self.error(ErrorKind::ProgramTooBig(self.size_limit)));
+        }
+        Ok(())
+    }
+
+    /// 编译 span 范围内的量词或分组，期间出的错都标在 span 上
+    fn spanned(
+        &mut self,
+        span: &Range<usize>,
+        compile: impl FnOnce(&mut Self) -> Result<Vec<Inst>, ParseError>,
+    ) -> Result<Vec<Inst>, ParseError> {
+        self.spans.push(span.clone());
+        let code = compile(self)?;
+        self.check_size(&code)?;
+        self.spans.pop();
+        Ok(code)
+    }
+
+    fn compile_ast(&mut self, ast: &Ast) -> Result<Vec<Inst>, ParseError> {
+        let code = match ast {
+            Ast::Empty => vec![],
+            Ast::Literal { ch, ignore_case } => vec![Self::literal(*ch, *ignore_case)],
+            Ast::Dot { newline: true } => vec![Inst::AnyChar],
+            Ast::Dot { newline: false } => vec![Inst::AnyCharExceptNewline],
+            Ast::Digit => vec![Inst::Digit],
+            Ast::Word => vec![Inst::MetaChar],
+            Ast::Class(set) => vec![Inst::CharClass(set.clone())],
+            Ast::Assertion(assertion) => vec![match assertion {
+                Assertion::Start => Inst::Start,
+                Assertion::End => Inst::End,
+                Assertion::StartLine => Inst::StartLine,
+                Assertion::EndLine => Inst::EndLine,
+                Assertion::EndBeforeNewline => Inst::EndBeforeNewline,
+                Assertion::WordBoundary => Inst::WordBoundary,
+                Assertion::NotWordBoundary => Inst::NotWordBoundary,
+            }],
+            Ast::BackRef {
+                group,
+                ignore_case: false,
+            } => vec![Inst::Ref(*group)],
+            Ast::BackRef {
+                group,
+                ignore_case: true,
+            } => vec![Inst::RefIgnoreCase(*group)],
+            Ast::Repetition(rep) => self.spanned(&rep.span, |c| c.compile_repetition(rep))?,
+            Ast::Group(group) => self.spanned(&group.span, |c| c.compile_group(group))?,
+            Ast::Concat(asts) => {
+                let mut code = vec![];
+                for ast in asts {
+                    code.extend(self.compile_ast(ast)?);
+                }
+                code
+            }
+            Ast::Alternation(asts) => {
+                let mut branches = vec![];
+                for ast in asts {
+                    branches.push(self.compile_ast(ast)?);
+                }
+                // 从后往前套 Split，前面的分支优先
+                let mut split_code = branches.pop().unwrap_or_default();
+                while let Some(mut new_branch) = branches.pop() {
+                    let jump = Self::emit_jump_forward(&split_code);
+                    new_branch.push(jump);
+                    split_code = Self::emit_split_code(new_branch, split_code);
+                }
+                split_code
+            }
+        };
+        // 每个节点编译完都检查，不等整个程序生成出来
+        self.check_size(&code)?;
+        Ok(code)
+    }
+
+    /// 忽略大小写的字符换成包含所有大小写形式的字符类
+    fn literal(ch: char, ignore_case: bool) -> Inst {
+        let variants = if ignore_case {
+            fold::case_variants(ch)
+        } else {
+            vec![ch]
+        };
+        if variants.len() == 1 {
+            return Inst::Char(ch);
+        }
+        let mut set = ClassSet::new();
+        for variant in variants {
+            set.push_char(variant);
+        }
+        Inst::CharClass(set)
+    }
+
+    fn compile_group(&mut self, group: &Group) -> Result<Vec<Inst>, ParseError> {
+        if let GroupKind::Capture { index, name } = &group.kind {
+            if self.group_names.len() <= *index {
+                self.group_names.resize(index + 1, None);
+            }
+            self.group_names[*index].clone_from(name);
+        }
+
+        let body = self.compile_ast(&group.ast)?;
+        let code = match group.kind {
+            GroupKind::Capture { index, .. } => {
+                let mut code = Vec::with_capacity(body.len() + 2);
+                code.push(Inst::GroupBegin(index));
+                code.extend(body);
+                code.push(Inst::GroupEnd(index));
+                code
+            }
+            GroupKind::NonCapture => body,
+            GroupKind::Atomic => Self::emit_atomic_code(body),
+            GroupKind::LookAhead { negate } => {
+                Self::emit_look_code(body, |len| Inst::LookAhead { negate, len })
+            }
+            GroupKind::LookBehind { negate } => {
+                // 解析时已经检查过，这里只是保险
+                let (min, max) = group
+                    .ast
+                    .length_bounds()
+                    .ok_or_else(|| self.error(ErrorKind::UnboundedLookBehind))?;
+                Self::emit_look_code(body, |len| Inst::LookBehind {
+                    negate,
+                    len,
+                    min,
+                    max,
+                })
+            }
+        };
+        Ok(code)
+    }
+
+    /// 展开后不大时，先把块重复 min 次，没有上限时再接一个循环，否则接 `max - min` 层嵌套的可选块。
+    /// 否则用计数器循环，没有上限时同样在后面接一个循环
+    fn compile_repetition(&mut self, rep: &Repetition) -> Result<Vec<Inst>, ParseError> {
+        let block = self.compile_ast(&rep.ast)?;
+        // 空的块重复多少次都是空的，不用循环 min 次去展开
+        if block.is_empty() {
+            return Ok(block);
+        }
+        let greedy = rep.greed != Greed::Lazy;
+        let optional = rep.max.map(|max| max.saturating_sub(rep.min));
+        let len = block.len();
+        let size = Self::size(&block);
+        let expanded = rep.min.saturating_mul(size).saturating_add(match optional {
+            None => size + 2,
+            Some(optional) => optional.saturating_mul(size + 1),
+        });
+        let mut code = vec![];
+
+        let counted = expanded > EXPAND_LIMIT && (self.backtrack || expanded > self.size_limit);
+        // Pike VM 里每个计数器值都是一个单独的状态，循环体里的每条指令都要乘上圈数，
+        // 循环体再大或者里面还有计数器时状态数和展开后一样多，只能当作超出上限
+        if counted && !self.backtrack && (len > EXPAND_LIMIT || block.iter().any(Inst::is_counter))
+        {
+            return Err(self.error(ErrorKind::ProgramTooBig(self.size_limit)));
+        }
+        if !counted {
+            // 先算出展开后的大小，超出上限就不去分配
+            self.check_limit(expanded)?;
+            for _ in 0..rep.min {
+                code.extend_from_slice(&block);
+            }
+            match optional {
+                None => code.extend(Self::emit_zero_or_more_code(block, greedy)),
+                Some(optional) => {
+                    code.extend(Self::emit_nested_optional_code(&block, optional, greedy))
+                }
+            }
+        } else {
+            match rep.max {
+                None => {
+                    if rep.min > 0 {
+                        code.extend(self.emit_counted_code(&block, rep.min, rep.min, greedy));
+                    }
+                    code.extend(Self::emit_zero_or_more_code(block, greedy));
+                }
+                Some(max) => code.extend(self.emit_counted_code(&block, rep.min, max, greedy)),
+            }
+        }
+
+        if rep.greed == Greed::Possessive {
+            code = Self::emit_atomic_code(code);
+        }
+        Ok(code)
+    }
+
+    /// count 层嵌套的可选块 (a(a(a)?)?)?：前一个没匹配后面的就都跳过，
+    /// 块能匹配空串时也不会有指数多种回溯的走法
+    fn emit_nested_optional_code(block: &[Inst], count: usize, greedy: bool) -> Vec<Inst> {
+        let total = count * (block.len() + 1);
+        let mut code = Vec::with_capacity(total);
+        for _ in 0..count {
+            // 跳过这一层就是跳过后面所有层，直接到末尾
+            let skip = (total - code.len()) as isize;
+            code.push(if greedy {
+                Inst::Split(1, skip)
+            } else {
+                Inst::Split(skip, 1)
+            });
+            code.extend_from_slice(block);
+        }
+        code
+    }
+
+    /// 计数器循环：`RepeatStart`、`Repeat`、循环体、`RepeatNext`
+    fn emit_counted_code(
+        &mut self,
+        block: &[Inst],
+        min: usize,
+        max: usize,
+        greedy: bool,
+    ) -> Vec<Inst> {
+        let slot = self.counters;
+        self.counters += 1;
+        let mut code = Vec::with_capacity(block.len() + 3);
+        code.push(Inst::RepeatStart(slot));
+        code.push(Inst::Repeat {
+            slot,
+            min,
+            max,
+            greedy,
+            len: block.len() + 2,
+        });
+        code.extend_from_slice(block);
+        code.push(Inst::RepeatNext {
+            slot,
+            offset: -(block.len() as isize) - 1,
+        });
+        code
+    }
+
+    fn emit_jump_forward(insts: &[Inst]) -> Inst {
+        Inst::Jump(insts.len() as isize + 1)
+    }
+
+    fn emit_jump_backward(insts: &[Inst]) -> Inst {
+        Inst::Jump(-(insts.len() as isize) - 1)
+    }
+
+    fn emit_split_code(branch1: Vec<Inst>, branch2: Vec<Inst>) -> Vec<Inst> {
+        let mut code = Vec::with_capacity(branch1.len() + branch2.len() + 1);
+        code.push(Inst::Split(1, branch1.len() as isize + 1));
+        code.extend(branch1);
+        code.extend(branch2);
+        code
+    }
+
+    /// 把代码包成原子的子程序：`Atomic` 之后是原代码，以 `SubMatch` 结束
+    fn emit_atomic_code(block: Vec<Inst>) -> Vec<Inst> {
+        let mut code = Vec::with_capacity(block.len() + 2);
+        code.push(Inst::Atomic(block.len() + 2));
+        code.extend(block);
+        code.push(Inst::SubMatch);
+        code
+    }
+
+    /// 环视断言：断言指令之后是子程序，以 `SubMatch` 结束
+    fn emit_look_code(block: Vec<Inst>, look: impl FnOnce(usize) -> Inst) -> Vec<Inst> {
+        let mut code = Vec::with_capacity(block.len() + 2);
+        code.push(look(block.len() + 2));
+        code.extend(block);
+        code.push(Inst::SubMatch);
+        code
+    }
+
+    /// 非贪婪：交换 Split 的两个分支，优先跳过
+    fn prefer_skip(mut code: Vec<Inst>) -> Vec<Inst> {
+        if let Some(Inst::Split(offset1, offset2)) = code.first_mut() {
+            std::mem::swap(offset1, offset2);
+        }
+        code
+    }
+
+    /// 循环开头是 Split，循环体紧跟在它后面，另一个分支是出口，循环体末尾的 Jump 跳回开头。
+    /// 某一圈没有消耗字符时，两个引擎都按这个形状找到出口，见 `VM::loop_exit`
+    fn emit_zero_or_more_code(block: Vec<Inst>, greedy: bool) -> Vec<Inst> {
+        let mut branch1 = block;
+        branch1.push(Self::emit_jump_backward(&branch1));
+        let code = Self::emit_split_code(branch1, vec![]);
+        if greedy {
+            code
+        } else {
+            Self::prefer_skip(code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regex::{
+        ast,
+        compiler::{Compiler, DEFAULT_SIZE_LIMIT},
+        ir::{Inst, Program},
+        parser::{ErrorKind, ParseError},
+    };
+
+    fn compile(pattern: &str, size_limit: usize) -> Result<Program, ParseError> {
+        Compiler::compile(&ast::parse(pattern).unwrap(), pattern, size_limit)
+    }
+
+    #[test]
+    fn test_compile_alternation() {
+        let program = compile("a|b", DEFAULT_SIZE_LIMIT).unwrap();
+        assert!(matches!(
+            program.instrs.as_slice(),
+            [
+                Inst::Split(1, 3),
+                Inst::Char('a'),
+                Inst::Jump(2),
+                Inst::Char('b'),
+                Inst::Match
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_compile_group_names() {
+        let program = compile("(a)(?<x>b)", DEFAULT_SIZE_LIMIT).unwrap();
+        assert_eq!(program.group_names, vec![None, None, Some("x".to_string())]);
+    }
+
+    #[test]
+    fn test_compile_counted() {
+        let instrs_of = |pattern: &str| compile(pattern, DEFAULT_SIZE_LIMIT).unwrap().instrs;
+        // 小的直接展开
+        let instrs = instrs_of("a{3}");
+        assert!(matches!(
+            instrs.as_slice(),
+            [
+                Inst::Char('a'),
+                Inst::Char('a'),
+                Inst::Char('a'),
+                Inst::Match
+            ]
+        ));
+        // 可选的部分嵌套展开，跳过一层就跳到末尾
+        let instrs = instrs_of("a{1,3}");
+        assert!(matches!(
+            instrs.as_slice(),
+            [
+                Inst::Char('a'),
+                Inst::Split(1, 4),
+                Inst::Char('a'),
+                Inst::Split(1, 2),
+                Inst::Char('a'),
+                Inst::Match
+            ]
+        ));
+        // 没有只能回溯执行的结构时，放得下就展开，留给 DFA
+        assert_eq!(
+            instrs_of("(?:abc){1000,5000}").len(),
+            1000 * 3 + 4000 * 4 + 1
+        );
+        assert!(!instrs_of(r"\d{1,100}").iter().any(Inst::is_counter));
+        assert!(!instrs_of("(?:a*){0,100}").iter().any(Inst::is_counter));
+        // 展开后超出上限时只有一份循环体
+        let instrs = instrs_of("(?:abc){1000,500000}");
+        assert!(matches!(
+            instrs.as_slice(),
+            [
+                Inst::RepeatStart(0),
+                Inst::Repeat {
+                    slot: 0,
+                    min: 1000,
+                    max: 500000,
+                    greedy: true,
+                    len: 5
+                },
+                Inst::Char('a'),
+                Inst::Char('b'),
+                Inst::Char('c'),
+                Inst::RepeatNext {
+                    slot: 0,
+                    offset: -4
+                },
+                Inst::Match
+            ]
+        ));
+        assert!(instrs_of("a{0,1000000000}").len() < 10);
+        assert!(instrs_of("(?:a?){1000000000}").len() < 10);
+        // 反正只能回溯执行时，大的也用计数器，嵌套的计数不会成倍增长
+        assert!(instrs_of(r"(a)\1(?:a*){0,100}")
+            .iter()
+            .any(Inst::is_counter));
+        assert!(instrs_of(r"(a)\1((a{10}){100}){1000}").len() < 40);
+        // 否则计数器循环的循环体很大或者还套着计数器时报错，标出外层的量词
+        let err = compile("x(?:(?:a|b){1,1000}){0,1000}", DEFAULT_SIZE_LIMIT).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ProgramTooBig(DEFAULT_SIZE_LIMIT));
+        assert_eq!(err.span, 1..28);
+        let err = compile("(?:a{0,2000000}){0,2000000}", DEFAULT_SIZE_LIMIT).unwrap_err();
+        assert_eq!(err.span, 0..27);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        assert!(compile(r"\w{100}x", 102).is_ok());
+        let err = compile(r"\w{100}x", 101).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ProgramTooBig(101));
+        // 只有加上最后的 Match 才超出，标出整个模式串
+        assert_eq!(err.span, 0..8);
+        // 超出上限的量词或分组
+        assert_eq!(compile(r"x(?:ab){50}y", 60).unwrap_err().span, 1..11);
+        assert_eq!(compile(r"x(ab{40}c{40})y", 60).unwrap_err().span, 1..14);
+        // 字符类按区间个数算大小
+        assert!(compile(r"[ac]x[a-c]", 7).is_ok());
+        assert!(compile(r"[ac]x[a-c]", 6).is_err());
+        assert!(!compile(r"[ac]{100}", 301)
+            .unwrap()
+            .instrs
+            .iter()
+            .any(Inst::is_counter));
+        assert!(compile(r"[ac]{100}", 299)
+            .unwrap()
+            .instrs
+            .iter()
+            .any(Inst::is_counter));
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+
+use crate::regex::{input::Text, ir::is_word_char, vm::VM, Inst};
+
+/// 缓存中最多保留的状态数，超过后整个缓存清空重建
+pub const DEFAULT_STATE_LIMIT: usize = 1024;
+
+type StateId = usize;
+
+const UNKNOWN: StateId = usize::MAX;
+// 读到这个字符之前就已经匹配成功了
+const MATCHED: StateId = usize::MAX - 1;
+
+/// 上一个字符的种类，断言 `^`、`\b` 要靠它判断
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Prev {
+    Start,
+    Word,
+    Newline,
+    Other,
+}
+
+impl Prev {
+    fn of(ch: char) -> Self {
+        if ch == '\n' {
+            Prev::Newline
+        } else if is_word_char(ch) {
+            Prev::Word
+        } else {
+            Prev::Other
+        }
+    }
+}
+
+/// DFA 状态：一组尚未展开空转移的 pc（按大小排序），以及上一个字符的种类。
+/// 断言还要看下一个字符，所以空转移在读到下一个字符（或输入结束）时才展开
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct StateKey {
+    insts: Vec<usize>,
+    prev: Prev,
+}
+
+struct State {
+    key: StateKey,
+    ascii: Box<[StateId; 128]>,
+    others: HashMap<char, StateId>,
+}
+
+/// 惰性 DFA 的状态缓存，可以在多次搜索之间复用
+pub struct Cache {
+    states: Vec<State>,
+    map: HashMap<StateKey, StateId>,
+    limit: usize,
+    // 缓存被清空过的次数
+    flushes: usize,
+    // 展开空转移时用的临时空间
+    stack: Vec<usize>,
+    seen: Vec<bool>,
+}
+
+impl Cache {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            states: Vec::new(),
+            map: HashMap::new(),
+            limit: limit.max(2),
+            flushes: 0,
+            stack: Vec::new(),
+            seen: Vec::new(),
+        }
+    }
+
+    fn flush(&mut self) {
+        self.states.clear();
+        self.map.clear();
+        self.flushes += 1;
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new(DEFAULT_STATE_LIMIT)
+    }
+}
+
+/// 惰性 DFA：边读输入边由 `Inst` 程序的 pc 集合构造状态，并缓存状态间的转移。
+/// 只回答是否匹配，不记录位置，所以分组指令都当作空转移处理。
+pub struct Dfa<'r, 'c> {
+    instrs: &'r [Inst],
+    cache: &'c mut Cache,
+}
+
+impl<'r, 'c> Dfa<'r, 'c> {
+    pub fn new(instrs: &'r [Inst], cache: &'c mut Cache) -> Self {
+        if cache.seen.len() < instrs.len() {
+            cache.seen.resize(instrs.len(), false);
+        }
+        Self { instrs, cache }
+    }
+
+    /// 反向引用需要知道捕获的内容，原子分组需要回溯，DFA 都无法表示。
+    /// `\Z` 要往后看两个字符，计数器循环的圈数也放不进状态里，都交给 Pike VM
+    pub fn supports(instrs: &[Inst]) -> bool {
+        !instrs.iter().any(|inst| {
+            inst.needs_backtrack() || inst.is_counter() || matches!(inst, Inst::EndBeforeNewline)
+        })
+    }
+
+    pub fn is_match(&mut self, text: &Text) -> bool {
+        let start = StateKey {
+            insts: vec![0],
+            prev: Prev::Start,
+        };
+        let mut current = self.add_state(start);
+        let mut cursor = 0;
+
+        while let Some(ch) = text.char_at(cursor) {
+            current = self.next_state(current, ch);
+            if current == MATCHED {
+                return true;
+            }
+            cursor += ch.len_utf8();
+        }
+
+        // 输入结束后 `$` 才能成立，要再展开一次
+        let key = self.cache.states[current].key.clone();
+        self.closure(&key, None).1
+    }
+
+    fn next_state(&mut self, mut current: StateId, ch: char) -> StateId {
+        let cached = {
+            let state = &self.cache.states[current];
+            if ch.is_ascii() {
+                state.ascii[ch as usize]
+            } else {
+                state.others.get(&ch).copied().unwrap_or(UNKNOWN)
+            }
+        };
+        if cached != UNKNOWN {
+            return cached;
+        }
+
+        let key = self.cache.states[current].key.clone();
+        let (insts, is_match) = self.closure(&key, Some(ch));
+        if is_match {
+            self.cache_transition(current, ch, MATCHED);
+            return MATCHED;
+        }
+        let mut next: Vec<usize> = insts
+            .into_iter()
+            .filter(|&pc| self.instrs[pc].is_match(&ch))
+            .map(|pc| pc + 1)
+            .collect();
+        // 每个位置都可以作为新的起点
+        next.push(0);
+        next.sort_unstable();
+        next.dedup();
+
+        let next_key = StateKey {
+            insts: next,
+            prev: Prev::of(ch),
+        };
+
+        if self.cache.states.len() >= self.cache.limit && !self.cache.map.contains_key(&next_key) {
+            self.cache.flush();
+            current = self.add_state(key);
+        }
+        let next = self.add_state(next_key);
+        self.cache_transition(current, ch, next);
+        next
+    }
+
+    fn cache_transition(&mut self, current: StateId, ch: char, next: StateId) {
+        let state = &mut self.cache.states[current];
+        if ch.is_ascii() {
+            state.ascii[ch as usize] = next;
+        } else {
+            state.others.insert(ch, next);
+        }
+    }
+
+    fn add_state(&mut self, key: StateKey) -> StateId {
+        if let Some(&id) = self.cache.map.get(&key) {
+            return id;
+        }
+        let id = self.cache.states.len();
+        self.cache.states.push(State {
+            key: key.clone(),
+            ascii: Box::new([UNKNOWN; 128]),
+            others: HashMap::new(),
+        });
+        self.cache.map.insert(key, id);
+        id
+    }
+
+    /// 展开空转移，返回会消耗字符的 pc，以及是否能到达 Match。
+    /// next 是下一个字符，None 表示已经到了输入末尾
+    fn closure(&mut self, key: &StateKey, next: Option<char>) -> (Vec<usize>, bool) {
+        let at_boundary = (key.prev == Prev::Word) != next.is_some_and(is_word_char);
+        let mut insts = vec![];
+        let mut is_match = false;
+        let mut visited = vec![];
+
+        let stack = &mut self.cache.stack;
+        let seen = &mut self.cache.seen;
+        stack.extend(key.insts.iter().rev());
+        while let Some(pc) = stack.pop() {
+            if seen[pc] {
+                continue;
+            }
+            seen[pc] = true;
+            visited.push(pc);
+
+            match &self.instrs[pc] {
+                Inst::Match => is_match = true,
+                Inst::Jump(offset) => stack.push(VM::jump_by(pc, *offset)),
+                Inst::Split(offset1, offset2) => {
+                    stack.push(VM::jump_by(pc, *offset2));
+                    stack.push(VM::jump_by(pc, *offset1));
+                }
+                Inst::GroupBegin(_) | Inst::GroupEnd(_) => stack.push(pc + 1),
+                Inst::Start if key.prev == Prev::Start => stack.push(pc + 1),
+                Inst::End if next.is_none() => stack.push(pc + 1),
+                Inst::StartLine if matches!(key.prev, Prev::Start | Prev::Newline) => {
+                    stack.push(pc + 1)
+                }
+                Inst::EndLine if matches!(next, None | Some('\n')) => stack.push(pc + 1),
+                Inst::WordBoundary if at_boundary => stack.push(pc + 1),
+                Inst::NotWordBoundary if !at_boundary => stack.push(pc + 1),
+                inst if inst.is_consuming() => insts.push(pc),
+                _ => {}
+            }
+        }
+        for pc in visited {
+            seen[pc] = false;
+        }
+
+        (insts, is_match)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regex::{
+        compiler::DEFAULT_SIZE_LIMIT,
+        dfa::{Cache, Dfa},
+        input::Text,
+        parser::Parser,
+    };
+
+    #[test]
+    fn test_dfa_match() {
+        let instrs = Parser::new(r"^I see \d+ (cat|dog)s?$")
+            .compile(DEFAULT_SIZE_LIMIT)
+            .unwrap()
+            .instrs;
+        let mut cache = Cache::default();
+        let mut dfa = Dfa::new(&instrs, &mut cache);
+        assert!(dfa.is_match(&Text::new("I see 42 dogs")));
+        assert!(!dfa.is_match(&Text::new("I see 42 dogs!")));
+        assert!(!dfa.is_match(&Text::new("you see 1 cat")));
+    }
+
+    #[test]
+    fn test_dfa_unanchored() {
+        let instrs = Parser::new("[^abc]pple")
+            .compile(DEFAULT_SIZE_LIMIT)
+            .unwrap()
+            .instrs;
+        let mut cache = Cache::default();
+        let mut dfa = Dfa::new(&instrs, &mut cache);
+        assert!(dfa.is_match(&Text::new("appleapplepple")));
+        assert!(!dfa.is_match(&Text::new("apple")));
+        assert!(dfa.is_match(&Text::new("äpple")));
+    }
+
+    #[test]
+    fn test_dfa_cache_flush() {
+        let instrs = Parser::new("(a|b)*a(a|b)(a|b)(a|b)(a|b)c")
+            .compile(DEFAULT_SIZE_LIMIT)
+            .unwrap()
+            .instrs;
+        let mut cache = Cache::new(4);
+        let mut dfa = Dfa::new(&instrs, &mut cache);
+        assert!(!dfa.is_match(&Text::new("abbbbababbbbbbbbbc")));
+        assert!(dfa.is_match(&Text::new("abbabababbbabbbabbbbc")));
+        assert!(cache.flushes > 0);
+    }
+}
@@ -0,0 +1,79 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+/// 简单大小写折叠的等价类：只看一对一的大小写映射（'ß' -> "SS" 这种不算），
+/// 互相可以通过映射到达的字符属于同一类，例如 'k'、'K' 和开尔文符号 'K'
+static ORBITS: OnceLock<HashMap<char, &'static [char]>> = OnceLock::new();
+
+// 有大小写的字符都在这之前（最后一段是阿德拉姆字母 U+1E900..U+1E94B）
+const CASED_END: u32 = 0x1F000;
+
+fn single(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    let c = chars.next()?;
+    chars.next().is_none().then_some(c)
+}
+
+pub fn orbits() -> &'static HashMap<char, &'static [char]> {
+    ORBITS.get_or_init(|| {
+        let mut edges: HashMap<char, Vec<char>> = HashMap::new();
+        for c in (0..CASED_END).filter_map(char::from_u32) {
+            let mapped = [single(c.to_lowercase()), single(c.to_uppercase())];
+            for other in mapped.into_iter().flatten().filter(|&other| other != c) {
+                edges.entry(c).or_default().push(other);
+                edges.entry(other).or_default().push(c);
+            }
+        }
+
+        let mut orbits = HashMap::new();
+        let mut chars: Vec<char> = edges.keys().copied().collect();
+        chars.sort_unstable();
+        for c in chars {
+            if orbits.contains_key(&c) {
+                continue;
+            }
+            let mut orbit = vec![c];
+            let mut i = 0;
+            while let Some(&cur) = orbit.get(i) {
+                for &next in edges[&cur].iter() {
+                    if !orbit.contains(&next) {
+                        orbit.push(next);
+                    }
+                }
+                i += 1;
+            }
+            orbit.sort_unstable();
+            // 整个程序只建一次表，泄漏掉换取 'static
+            let orbit: &'static [char] = Vec::leak(orbit);
+            for &member in orbit {
+                orbits.insert(member, orbit);
+            }
+        }
+        orbits
+    })
+}
+
+/// 与 c 大小写等价的所有字符（包括 c 自身），按码点排序
+pub fn case_variants(c: char) -> Vec<char> {
+    orbits()
+        .get(&c)
+        .map_or_else(|| vec![c], |orbit| orbit.to_vec())
+}
+
+/// 折叠后的代表字符，等价的字符折叠结果相同
+pub fn simple_fold(c: char) -> char {
+    orbits().get(&c).map_or(c, |orbit| orbit[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regex::fold::*;
+
+    #[test]
+    fn test_case_variants() {
+        assert_eq!(case_variants('a'), vec!['A', 'a']);
+        assert_eq!(case_variants('k'), vec!['K', 'k', '\u{212A}']);
+        assert_eq!(case_variants('σ'), vec!['Σ', 'ς', 'σ']);
+        assert_eq!(case_variants('ß'), vec!['ß', 'ẞ']);
+        assert_eq!(case_variants('1'), vec!['1']);
+        assert_eq!(simple_fold('\u{17F}'), simple_fold('s'));
+    }
+}
@@ -0,0 +1,263 @@
+//! 随机生成模式串和输入，检查解析、编译和匹配都不会 panic，出错只能返回 Err 或者不匹配
+
+use crate::regex::{
+    ast, input::Text, parser::NEST_LIMIT, pikevm::PikeVM, ParseError, Regex, RegexBuilder,
+};
+use std::panic::{self, AssertUnwindSafe};
+
+/// xorshift64*，种子固定，失败时可以复现
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+}
+
+const ATOMS: &[&str] = &[
+    "a",
+    "b",
+    "ab",
+    "é",
+    "中",
+    "_",
+    "1",
+    ".",
+    r"\d",
+    r"\w",
+    r"\s",
+    r"\D",
+    r"\W",
+    r"\S",
+    r"\b",
+    r"\B",
+    "^",
+    "$",
+    r"\A",
+    r"\z",
+    r"\Z",
+    r"\n",
+    r"\x61",
+    r"\u{4E2D}",
+    r"\.",
+    "[a-c]",
+    "[^ab]",
+    "[[:alpha:]]",
+    "[[:^digit:]é]",
+    r"\p{L}",
+    r"\P{Han}",
+    "[a-z&&[^b]]",
+    "[ab--b]",
+    "[a~~[b1]]",
+    r"\1",
+    r"\2",
+    r"\k<x>",
+];
+
+const GROUPS: &[&str] = &[
+    "(", "(?:", "(?>", "(?=", "(?!", "(?<=", "(?<!", "(?<x>", "(?P<y>", "(?i:", "(?m:", "(?s:",
+    "(?x:", "(?-i:",
+];
+
+const FLAGS: &[&str] = &["(?i)", "(?m)", "(?s)", "(?x)", "(?im)", "(?-s)"];
+
+const QUANTIFIERS: &[&str] = &[
+    "*", "+", "?", "*?", "+?", "??", "*+", "++", "?+", "{2}", "{0,2}", "{1,}", "{2,1}", "{,1}",
+    "{3,200}", "{150}+", "{0,300}?", "{129,}",
+];
+
+// 大多是元字符，拼出来的大多是不合法的模式串
+const SYNTAX: &[&str] = &[
+    "(", ")", "[", "]", "{", "}", "|", "*", "+", "?", ".", "^", "$", r"\", "-", "&", "~", ":", "<",
+    ">", "=", "!", ",", "0", "1", "9", "a", "b", "P", "p", "d", "k", "x", "u", "#", " ", "\n", "é",
+    "中",
+];
+
+const TEXT: &[&str] = &["a", "b", "A", "1", "_", " ", "\n", "é", "É", "中", "-"];
+
+fn gen_pattern(rng: &mut Rng, depth: usize) -> String {
+    let mut pattern = String::new();
+    for _ in 0..=rng.below(3) {
+        if rng.chance(10) {
+            pattern.push_str(rng.pick(FLAGS));
+        }
+        if depth > 0 && rng.chance(30) {
+            pattern.push_str(rng.pick(GROUPS));
+            pattern.push_str(&gen_pattern(rng, depth - 1));
+            pattern.push(')');
+        } else {
+            pattern.push_str(rng.pick(ATOMS));
+        }
+        if rng.chance(40) {
+            pattern.push_str(rng.pick(QUANTIFIERS));
+        }
+    }
+    if rng.chance(20) {
+        pattern.push('|');
+        pattern.push_str(&gen_pattern(rng, depth.saturating_sub(1)));
+    }
+    pattern
+}
+
+fn gen_garbage(rng: &mut Rng) -> String {
+    (0..rng.below(16)).map(|_| rng.pick(SYNTAX)).collect()
+}
+
+/// 在合法的模式串里随机删掉或者插入一个字符
+fn mutate(rng: &mut Rng, pattern: &str) -> String {
+    let mut chars: Vec<char> = pattern.chars().collect();
+    let at = rng.below(chars.len() + 1);
+    if rng.chance(50) && at < chars.len() {
+        chars.remove(at);
+    } else {
+        chars.splice(at..at, rng.pick(SYNTAX).chars());
+    }
+    chars.into_iter().collect()
+}
+
+fn gen_text(rng: &mut Rng) -> String {
+    (0..rng.below(10)).map(|_| rng.pick(TEXT)).collect()
+}
+
+/// 编译并在每个输入上跑一遍所有接口，panic 时报告是哪个模式串
+fn check(pattern: &str, inputs: &[String], case_insensitive: bool, multi_line: bool) {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        if let Err(err) = ast::parse(pattern) {
+            let _ = format!("{:#}", err);
+        }
+        let re = RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .multi_line(multi_line)
+            .build();
+        let re = match re {
+            Ok(re) => re,
+            Err(err) => {
+                // 模式串的错误都应该是带位置的 ParseError
+                let err = err.downcast_ref::<ParseError>().expect("不是 ParseError");
+                let _ = format!("{:#}", err);
+                return;
+            }
+        };
+        // size_limit 小时大的量词都用计数器循环，两个引擎同样要一致
+        let counted = RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .multi_line(multi_line)
+            .size_limit(300)
+            .build();
+        for input in inputs {
+            // DFA 和回溯/Pike VM 的结论要一致
+            assert_eq!(re.is_match(input), re.find(input).is_some());
+            compare_engines(&re, input);
+            if let Ok(counted) = &counted {
+                assert_eq!(counted.is_match(input), re.is_match(input));
+                compare_engines(counted, input);
+            }
+            if let Some(caps) = re.captures(input) {
+                for i in 0..caps.len() {
+                    let _ = caps.get(i);
+                }
+            }
+            for m in re.find_iter(input) {
+                assert!(m.start() <= m.end());
+            }
+        }
+    }));
+    if result.is_err() {
+        panic!(
+            "模式串 {:?}（i={}, m={}）在输入 {:?} 上 panic",
+            pattern, case_insensitive, multi_line, inputs
+        );
+    }
+}
+
+/// Pike VM 能执行的程序也交给回溯的 VM 跑一遍，从每个起点找到的匹配和捕获位置都要相同
+fn compare_engines(re: &Regex, input: &str) {
+    if !PikeVM::supports(&re.instrs) {
+        return;
+    }
+    let text = Text::new(input);
+    let nslots = re.captures_len() * 2;
+    for (start, _) in input.char_indices().chain([(input.len(), ' ')]) {
+        let mut pike = vec![None; nslots];
+        let mut backtrack = vec![None; nslots];
+        let found = PikeVM::new(&re.instrs).search(&text, start, &mut pike);
+        assert_eq!(found, re.search_backtrack(&text, start, &mut backtrack));
+        if found {
+            assert_eq!(pike, backtrack, "从 {} 开始匹配 {:?}", start, input);
+        }
+    }
+}
+
+#[test]
+fn test_random_patterns() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    for _ in 0..3000 {
+        let pattern = match rng.below(4) {
+            0 => gen_garbage(&mut rng),
+            1 => {
+                let pattern = gen_pattern(&mut rng, 2);
+                mutate(&mut rng, &pattern)
+            }
+            _ => gen_pattern(&mut rng, 2),
+        };
+        let inputs: Vec<String> = (0..4).map(|_| gen_text(&mut rng)).collect();
+        check(&pattern, &inputs, rng.chance(20), rng.chance(20));
+    }
+}
+
+#[test]
+fn test_deep_nesting() {
+    assert!(Regex::new(&"(".repeat(100_000)).is_err());
+    assert!(Regex::new(&"[".repeat(100_000)).is_err());
+    assert!(Regex::new(&"(?:a|".repeat(100_000)).is_err());
+
+    let pattern = format!("{}a{}", "(".repeat(NEST_LIMIT), ")".repeat(NEST_LIMIT));
+    let re = Regex::new(&pattern).unwrap();
+    assert_eq!(re.find("ba").map(|m| m.start()), Some(1));
+
+    let err = crate::regex::parser::Parser::new(&format!("({pattern})"))
+        .parse()
+        .unwrap_err();
+    assert_eq!(err.code(), "nest_limit_exceeded");
+    assert_eq!(err.offset(), NEST_LIMIT);
+}
+
+#[test]
+fn test_long_input_backtrack() {
+    // 回溯的 VM 不再随输入长度递归
+    let text = "a".repeat(100_000);
+    let re = Regex::new(r"^(a)(?:\1)*$").unwrap();
+    assert!(re.is_match(&text));
+    let re = Regex::new(r"(?>a+)(?<=a)$").unwrap();
+    assert_eq!(re.find(&text).map(|m| m.end()), Some(text.len()));
+}
+
+#[test]
+fn test_empty_loop() {
+    // 循环体可以不消耗字符时不能原地打转
+    let re = Regex::new(r"((?:a?)*)\1b").unwrap();
+    assert_eq!(re.find("aab").map(|m| m.as_str()), Some("aab"));
+    assert!(!re.is_match("ac"));
+    let re = Regex::new(r"(?:(?=a))*(a)\1").unwrap();
+    assert!(re.is_match("aa"));
+    // 第一圈走空分支就离开循环，原子分组不再回头
+    let re = Regex::new(r"(?>(?:|a)*)x").unwrap();
+    assert_eq!(re.find("aax").map(|m| m.as_str()), Some("x"));
+}
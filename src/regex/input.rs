@@ -1,4 +1,4 @@
-use std::ops::Index;
+use crate::regex::ir::is_word_char;
 
 pub struct Text<'t> {
     text: &'t str,
@@ -16,29 +16,53 @@ impl<'t> Text<'t> {
         self.text.get(start..end).unwrap_or("")
     }
 
-    pub fn matchwith_at(&self, index: usize, ch: &char) -> bool {
-        self.char_at(index).is_some_and(|c| c == *ch)
-    }
-
     pub fn char_at(&self, index: usize) -> Option<char> {
         self.text.get(index..)?.chars().next()
     }
     pub fn is_end(&self, index: usize) -> bool {
-        self.text.get(index..).is_none_or(|s| s.is_empty())
+        self.text.get(index..).map_or(true, |s| s.is_empty())
+    }
+
+    /// 下一个字符的位置，已经在文本末尾时返回 None
+    pub fn next_cursor(&self, current_cursor: usize) -> Option<usize> {
+        let ch = self.char_at(current_cursor)?;
+        Some(current_cursor + ch.len_utf8())
     }
 
-    pub fn next_cursor_unsafe(&self, current_cursor: usize) -> usize {
-        let slice = self.text.get(current_cursor..).unwrap();
-        // eprintln!(">>> {}", slice);
-        let current_len = slice.chars().next().unwrap().len_utf8();
-        current_cursor + current_len
+    /// 前后两个字符一个是单词字符、一个不是（或者在文本边界上）
+    pub fn is_word_boundary(&self, index: usize) -> bool {
+        let before = self
+            .prev_cursor(index)
+            .and_then(|prev| self.char_at(prev))
+            .is_some_and(is_word_char);
+        let after = self.char_at(index).is_some_and(is_word_char);
+        before != after
+    }
+
+    /// 在文本开头，或者前一个字符是 '\n'
+    pub fn is_line_start(&self, index: usize) -> bool {
+        index == 0 || self.text.as_bytes().get(index - 1) == Some(&b'\n')
+    }
+
+    /// 在文本末尾，或者当前字符是 '\n'
+    pub fn is_line_end(&self, index: usize) -> bool {
+        self.is_end(index) || self.text.as_bytes().get(index) == Some(&b'\n')
+    }
+
+    /// 在文本末尾，或者后面只剩一个 '\n'
+    pub fn is_end_before_newline(&self, index: usize) -> bool {
+        matches!(self.text.get(index..), Some("" | "\n") | None)
+    }
+
+    /// 上一个字符的位置，已经在文本开头时返回 None
+    pub fn prev_cursor(&self, current_cursor: usize) -> Option<usize> {
+        let ch = self.text.get(..current_cursor)?.chars().next_back()?;
+        Some(current_cursor - ch.len_utf8())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use anyhow::Error;
-
     use crate::regex::input::*;
 
     #[test]
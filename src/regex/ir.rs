@@ -1,43 +1,126 @@
-use std::collections::HashSet;
+use crate::regex::{class::ClassSet, unicode};
+
+/// 编译好的程序：指令序列，以及各捕获组的名字（下标是组号，第 0 组是整个匹配）
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub instrs: Vec<Inst>,
+    pub group_names: Vec<Option<String>>,
+}
 
 #[derive(Debug, Clone)]
 pub enum Inst {
     Char(char), // one char
     AnyChar,
+    AnyCharExceptNewline,
     Start,
     End,
+    StartLine,        // 多行模式的 '^'：文本开头或 '\n' 之后
+    EndLine,          // 多行模式的 '$'：文本末尾或 '\n' 之前
+    EndBeforeNewline, // '\Z'：文本末尾，或者末尾的最后一个 '\n' 之前
     Match,
     Jump(isize),
     Split(isize, isize),
-    CharClass { negated: bool, chars: HashSet<char> },
+    CharClass(ClassSet),
     Digit,
-    MetaChar, // \w : alpha digit '_'
+    MetaChar,        // \w : alpha digit '_'
+    WordBoundary,    // \b
+    NotWordBoundary, // \B
+
+    GroupBegin(usize),    // (
+    GroupEnd(usize),      // )
+    Ref(usize),           // '\1'
+    RefIgnoreCase(usize), // i 模式下的 '\1'，按简单大小写折叠比较
 
-    GroupBegin(usize), // (
-    GroupEnd(usize),   // )
-    Ref(usize),        // '\1'
+    // (?>...)：先单独匹配 pc+1 开始、以 SubMatch 结尾的子程序，
+    // 成功后不再回溯进去，从 pc+len 继续
+    Atomic(usize),
+    // (?=...)、(?!...)：子程序的结构同上，但不消耗输入
+    LookAhead {
+        negate: bool,
+        len: usize,
+    },
+    // (?<=...)、(?<!...)：子程序要恰好匹配到当前位置，长度在 [min, max] 个字符之间
+    LookBehind {
+        negate: bool,
+        len: usize,
+        min: usize,
+        max: usize,
+    },
+    SubMatch, // 子程序匹配成功
+
+    // 展开后太大的 {n,m} 用计数器循环，不把循环体复制 m 份。
+    // RepeatStart 把第 slot 个计数器清零，Repeat 是循环开头，循环体之后是 RepeatNext
+    RepeatStart(usize),
+    // 已经转了 min 圈之前必须进入循环体，到了 max 圈必须离开，离开时跳到 pc+len
+    Repeat {
+        slot: usize,
+        min: usize,
+        max: usize,
+        greedy: bool,
+        len: usize,
+    },
+    // 计数器加一，跳回 Repeat
+    RepeatNext {
+        slot: usize,
+        offset: isize,
+    },
 }
 
 impl Inst {
+    /// 只有回溯的 VM 能执行的指令
+    pub fn needs_backtrack(&self) -> bool {
+        matches!(
+            self,
+            Inst::Ref(_)
+                | Inst::RefIgnoreCase(_)
+                | Inst::Atomic(_)
+                | Inst::LookAhead { .. }
+                | Inst::LookBehind { .. }
+                | Inst::SubMatch
+        )
+    }
+
+    /// 计数器循环的指令，执行时每个线程都要带着自己的计数器
+    pub fn is_counter(&self) -> bool {
+        matches!(
+            self,
+            Inst::RepeatStart(_) | Inst::Repeat { .. } | Inst::RepeatNext { .. }
+        )
+    }
+
+    /// 是否会消耗一个字符，其余指令都是空转移或断言
+    pub fn is_consuming(&self) -> bool {
+        matches!(
+            self,
+            Inst::Char(_)
+                | Inst::AnyChar
+                | Inst::AnyCharExceptNewline
+                | Inst::CharClass(_)
+                | Inst::Digit
+                | Inst::MetaChar
+        )
+    }
+
     pub fn is_match(&self, ch: &char) -> bool {
         match self {
             Inst::Char(c) => *c == *ch,
             Inst::AnyChar => true,
-            Inst::Start => false,
-            Inst::End => false,
+            Inst::AnyCharExceptNewline => *ch != '\n',
             Inst::Match => true,
-            Inst::Jump(_) => false,
-            Inst::Split(_, _) => false,
-            Inst::CharClass { negated, chars } => {
-                if *negated {
-                    !chars.contains(ch)
-                } else {
-                    chars.contains(ch)
-                }
-            }
-            Inst::Digit => ch.is_digit(10),
-            Inst::MetaChar => ch.is_alphanumeric() || *ch == '_',
-            _ => todo!("尚未实现指令的匹配逻辑"),
+            Inst::CharClass(set) => set.contains(*ch),
+            Inst::Digit => ch.is_ascii_digit(),
+            Inst::MetaChar => is_word_char(*ch),
+            // 断言和控制指令不消耗字符
+            _ => false,
         }
     }
 }
+
+/// `\w` 和 `\b` 共用的单词字符：字母、数字和 '_'
+pub fn is_word_char(ch: char) -> bool {
+    if ch.is_ascii() {
+        ch.is_ascii_alphanumeric() || ch == '_'
+    } else {
+        unicode::contains(unicode::PERL_WORD, ch)
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// 错误信息使用的语言，默认英文
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Lang {
+    #[default]
+    English,
+    Chinese,
+}
+
+static LANG: AtomicU8 = AtomicU8::new(Lang::English as u8);
+
+impl Lang {
+    /// 按 `zh_CN.UTF-8` 这样的 locale 名选择语言，不认识的都用英文
+    pub fn from_locale(locale: &str) -> Self {
+        if locale.starts_with("zh") {
+            Lang::Chinese
+        } else {
+            Lang::English
+        }
+    }
+}
+
+/// 设置全局的错误信息语言，之后格式化的错误都使用这种语言
+pub fn set_lang(lang: Lang) {
+    LANG.store(lang as u8, Ordering::Relaxed);
+}
+
+pub fn lang() -> Lang {
+    match LANG.load(Ordering::Relaxed) {
+        1 => Lang::Chinese,
+        _ => Lang::English,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regex::lang::*;
+
+    #[test]
+    fn test_from_locale() {
+        assert_eq!(Lang::from_locale("zh_CN.UTF-8"), Lang::Chinese);
+        assert_eq!(Lang::from_locale("zh_TW"), Lang::Chinese);
+        assert_eq!(Lang::from_locale("en_US.UTF-8"), Lang::English);
+        assert_eq!(Lang::from_locale("C"), Lang::English);
+    }
+}
@@ -1,426 +1,1067 @@
-use crate::regex::Inst;
-use std::{
-    collections::HashSet,
-    iter::Peekable,
-    ops::{Add, Sub},
-    str::Chars,
-    usize,
+use crate::regex::{
+    ast::{Assertion, Ast, Greed, Group, GroupKind, Repetition},
+    class::ClassSet,
+    compiler::Compiler,
+    ir::Program,
+    lang::{self, Lang},
+    unicode,
 };
+use std::{fmt, iter::Peekable, ops::Range, str::CharIndices};
+use unicode_width::UnicodeWidthStr;
 
-use thiserror::{self, Error};
+/// 解析错误：错误类型、出错语法在模式串里的字节范围，以及模式串本身。
+/// `{}` 只输出错误信息，`{:#}` 还会列出模式串，并在出错的位置下面画上 `^~~~`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ErrorKind,
+    pub span: Range<usize>,
+    pub pattern: String,
+}
 
-#[derive(Debug, Error)]
-pub enum ParseError {
-    #[error("未知的转义字符: '\\{0}'")]
-    UnknownEscape(char),
+impl ParseError {
+    pub fn new(kind: ErrorKind, span: Range<usize>, pattern: &str) -> Self {
+        Self {
+            kind,
+            span,
+            pattern: pattern.to_string(),
+        }
+    }
 
-    #[error("不完整的转义字符")]
-    IncompletedEscape,
+    /// 出错位置的字节偏移
+    pub fn offset(&self) -> usize {
+        self.span.start
+    }
 
-    #[error("未闭合的字符类，缺少']'")]
-    UnclosedCharClass,
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if !f.alternate() {
+            return Ok(());
+        }
+        // 只列出 span 开始的那一行，x 模式的模式串可能有好几行
+        let pattern = self.pattern.as_str();
+        let start = self.span.start.min(pattern.len());
+        let (Some(before), Some(after)) = (pattern.get(..start), pattern.get(start..)) else {
+            // span 不在字符边界上，没法画出位置
+            return Ok(());
+        };
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = after.find('\n').map_or(pattern.len(), |i| start + i);
+        let end = self.span.end.clamp(start, line_end);
+        // 按终端显示宽度对齐，中日韩文字占两列
+        let column = before[line_start..].width();
+        let width = pattern.get(start..end).map_or(1, |s| s.width().max(1));
+        write!(
+            f,
+            "\n    {}\n    {}^{}",
+            &pattern[line_start..line_end],
+            " ".repeat(column),
+            "~".repeat(width - 1)
+        )
+    }
+}
 
-    #[error("未闭合的()，缺少')'")]
+impl std::error::Error for ParseError {}
+
+/// 解析错误的类型。`code` 是稳定的机器可读代码，`message` 是给人看的错误信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnknownEscape(char),
+    IncompletedEscape,
+    UnclosedCharClass,
     UnclosedGroup,
+    // 花括号里的原始内容，例如 `{3,1}` 里的 "3,1"
+    InvalidQuantifier(String),
+    UnknownGroupFlag(char),
+    InvalidGroupName(String),
+    DuplicateGroupName(String),
+    UnknownGroupName(String),
+    // \n 引用的组号比模式串里的分组数还大
+    UnknownGroup(usize),
+    UnboundedLookBehind,
+    UnexpectedMetaChar(char),
+    InvalidCodePoint(String),
+    UnknownProperty(String),
+    InvalidRange(char, char),
+    InvalidPosixClass(String),
+    UnknownFlag(char),
+    NestTooDeep,
+    // 编译后的指令数超过上限
+    ProgramTooBig(usize),
+}
 
-    #[error("'$'后面不允许出现其它字符")]
-    MisplacedAnchor,
+impl ErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::UnknownEscape(_) => "unknown_escape",
+            ErrorKind::IncompletedEscape => "incomplete_escape",
+            ErrorKind::UnclosedCharClass => "unclosed_class",
+            ErrorKind::UnclosedGroup => "unclosed_group",
+            ErrorKind::InvalidQuantifier(_) => "invalid_quantifier",
+            ErrorKind::UnknownGroupFlag(_) => "unknown_group_flag",
+            ErrorKind::InvalidGroupName(_) => "invalid_group_name",
+            ErrorKind::DuplicateGroupName(_) => "duplicate_group_name",
+            ErrorKind::UnknownGroupName(_) => "unknown_group_name",
+            ErrorKind::UnknownGroup(_) => "unknown_group",
+            ErrorKind::UnboundedLookBehind => "unbounded_look_behind",
+            ErrorKind::UnexpectedMetaChar(_) => "unexpected_meta_char",
+            ErrorKind::InvalidCodePoint(_) => "invalid_code_point",
+            ErrorKind::UnknownProperty(_) => "unknown_property",
+            ErrorKind::InvalidRange(..) => "invalid_range",
+            ErrorKind::InvalidPosixClass(_) => "invalid_posix_class",
+            ErrorKind::UnknownFlag(_) => "unknown_flag",
+            ErrorKind::NestTooDeep => "nest_limit_exceeded",
+            ErrorKind::ProgramTooBig(_) => "size_limit_exceeded",
+        }
+    }
 
-    #[error("指令回填错误")]
-    PatchError,
+    pub fn message(&self, lang: Lang) -> String {
+        match lang {
+            Lang::English => self.english(),
+            Lang::Chinese => self.chinese(),
+        }
+    }
 
-    #[error("捕获组序号不匹配")]
-    GroupNumMissError,
+    fn english(&self) -> String {
+        match self {
+            ErrorKind::UnknownEscape(c) => format!("unknown escape sequence: '\\{c}'"),
+            ErrorKind::IncompletedEscape => "incomplete escape sequence".to_string(),
+            ErrorKind::UnclosedCharClass => "unclosed character class, missing ']'".to_string(),
+            ErrorKind::UnclosedGroup => "unclosed group, missing ')'".to_string(),
+            ErrorKind::InvalidQuantifier(_) => "invalid repetition count".to_string(),
+            ErrorKind::UnknownGroupFlag(c) => format!("unknown group syntax: '(?{c}'"),
+            ErrorKind::InvalidGroupName(name) => format!("invalid group name: '{name}'"),
+            ErrorKind::DuplicateGroupName(name) => format!("duplicate group name: '{name}'"),
+            ErrorKind::UnknownGroupName(name) => format!("undefined group name: '{name}'"),
+            ErrorKind::UnknownGroup(group) => format!("reference to undefined group: '\\{group}'"),
+            ErrorKind::UnboundedLookBehind => {
+                "look-behind assertion must have a bounded length".to_string()
+            }
+            ErrorKind::UnexpectedMetaChar(c) => {
+                format!("unexpected metacharacter '{c}', use '\\{c}' to match it literally")
+            }
+            ErrorKind::InvalidCodePoint(digits) => format!("invalid code point: '{digits}'"),
+            ErrorKind::UnknownProperty(name) => format!("unknown Unicode property: '{name}'"),
+            ErrorKind::InvalidRange(lo, hi) => format!("invalid character range: '{lo}-{hi}'"),
+            ErrorKind::InvalidPosixClass(name) => format!("invalid POSIX class: '{name}'"),
+            ErrorKind::UnknownFlag(c) => format!("unknown flag: '{c}'"),
+            ErrorKind::NestTooDeep => {
+                format!("groups and classes nest too deeply, the limit is {NEST_LIMIT}")
+            }
+            ErrorKind::ProgramTooBig(limit) => {
+                format!("compiled program exceeds the size limit of {limit}")
+            }
+        }
+    }
 
-    #[error("非法的量词数字")]
-    InvalidQuantifier(String),
+    fn chinese(&self) -> String {
+        match self {
+            ErrorKind::UnknownEscape(c) => format!("未知的转义字符: '\\{c}'"),
+            ErrorKind::IncompletedEscape => "不完整的转义字符".to_string(),
+            ErrorKind::UnclosedCharClass => "未闭合的字符类，缺少']'".to_string(),
+            ErrorKind::UnclosedGroup => "未闭合的()，缺少')'".to_string(),
+            ErrorKind::InvalidQuantifier(_) => "非法的量词数字".to_string(),
+            ErrorKind::UnknownGroupFlag(c) => format!("未知的分组语法: '(?{c}'"),
+            ErrorKind::InvalidGroupName(name) => format!("非法的分组名: '{name}'"),
+            ErrorKind::DuplicateGroupName(name) => format!("重复的分组名: '{name}'"),
+            ErrorKind::UnknownGroupName(name) => format!("未定义的分组名: '{name}'"),
+            ErrorKind::UnknownGroup(group) => format!("引用了不存在的分组: '\\{group}'"),
+            ErrorKind::UnboundedLookBehind => "后顾断言的长度必须有上限".to_string(),
+            ErrorKind::UnexpectedMetaChar(c) => {
+                format!("元字符 '{c}' 出现在不该出现的位置，要匹配它本身请用 '\\{c}'")
+            }
+            ErrorKind::InvalidCodePoint(digits) => format!("非法的码点: '{digits}'"),
+            ErrorKind::UnknownProperty(name) => format!("未知的 Unicode 属性: '{name}'"),
+            ErrorKind::InvalidRange(lo, hi) => format!("非法的字符范围: '{lo}-{hi}'"),
+            ErrorKind::InvalidPosixClass(name) => format!("非法的 POSIX 字符类: '{name}'"),
+            ErrorKind::UnknownFlag(c) => format!("未知的标志: '{c}'"),
+            ErrorKind::NestTooDeep => format!("分组和字符类嵌套太深，最多 {NEST_LIMIT} 层"),
+            ErrorKind::ProgramTooBig(limit) => format!("编译后的程序超过了大小上限 {limit}"),
+        }
+    }
+}
+
+/// 使用 `set_lang` 设置的语言
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message(lang::lang()))
+    }
+}
+
+/// `(` 之后的分组语法
+enum GroupSyntax {
+    // (...) 或命名分组 (?P<name>...)、(?<name>...)
+    Capture(Option<String>),
+    // (?:...)
+    NonCapture,
+    // (?>...)
+    Atomic,
+    // (?=...) 或 (?!...)
+    LookAhead { negate: bool },
+    // (?<=...) 或 (?<!...)
+    LookBehind { negate: bool },
+    // (?i) 之类，修改当前分组剩余部分的标志
+    SetFlags(Flags),
+    // (?i:...)，标志只在这个分组内有效
+    Scoped(Flags),
+}
+
+/// 字符类里的集合运算
+#[derive(Debug, Clone, Copy)]
+enum SetOp {
+    Intersection,        // &&
+    Difference,          // --
+    SymmetricDifference, // ~~
+}
+
+/// 内联标志
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub case_insensitive: bool, // i
+    pub multi_line: bool,       // m：'^'/'$' 匹配每一行的开头和结尾
+    pub dot_all: bool,          // s：'.' 也匹配 '\n'
+    pub verbose: bool,          // x：忽略空白，'#' 开始到行尾是注释
+}
+
+impl Flags {
+    fn set(&mut self, flag: char, on: bool) -> Result<(), ErrorKind> {
+        match flag {
+            'i' => self.case_insensitive = on,
+            'm' => self.multi_line = on,
+            's' => self.dot_all = on,
+            'x' => self.verbose = on,
+            c => return Err(ErrorKind::UnknownFlag(c)),
+        }
+        Ok(())
+    }
+}
+
+/// 逐个读取字符，同时记下读到的字节位置
+struct Cursor<'p> {
+    chars: Peekable<CharIndices<'p>>,
+    len: usize,
+}
+
+impl Cursor<'_> {
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek().map(|(_, c)| c)
+    }
+
+    fn next_if(&mut self, func: impl FnOnce(&char) -> bool) -> Option<char> {
+        self.chars.next_if(|(_, c)| func(c)).map(|(_, c)| c)
+    }
+
+    fn next_if_eq(&mut self, expected: &char) -> Option<char> {
+        self.next_if(|c| c == expected)
+    }
+
+    /// 下一个字符的字节位置，读完时是模式串的长度
+    fn pos(&mut self) -> usize {
+        self.chars.peek().map_or(self.len, |(i, _)| *i)
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        self.chars.next().map(|(_, c)| c)
+    }
 }
 
 pub struct Parser<'p> {
-    chars: Peekable<Chars<'p>>,
-    instrs: Vec<Inst>,
-    patch_list: Vec<usize>,
-    num_stack: Vec<usize>,
+    pattern: &'p str,
+    chars: Cursor<'p>,
     next_group_num: usize,
+    // 下标是组号，第 0 组是整个匹配，没有名字
+    group_names: Vec<Option<String>>,
+    flags: Flags,
+    // 当前所在的分组和字符类的嵌套层数
+    depth: usize,
+    // 按组号的反向引用和它们的位置，解析完才知道一共有几个分组
+    back_refs: Vec<(usize, Range<usize>)>,
+    // 按名字的反向引用和它们的位置，同样可以引用后面才定义的分组
+    named_refs: Vec<(String, Range<usize>)>,
 }
 
+/// 分组和字符类最多嵌套的层数，解析和编译都是递归的，太深会把栈用完
+pub const NEST_LIMIT: usize = 100;
+
 impl<'p> Parser<'p> {
     pub fn new(pattern: &'p str) -> Self {
+        Self::with_flags(pattern, Flags::default())
+    }
+
+    /// flags 是整个模式串的初始标志，内联标志在此基础上修改
+    pub fn with_flags(pattern: &'p str, flags: Flags) -> Self {
         Parser {
-            chars: pattern.chars().peekable(),
-            instrs: Vec::new(),
-            patch_list: Vec::new(),
-            num_stack: Vec::new(),
+            pattern,
+            chars: Cursor {
+                chars: pattern.char_indices().peekable(),
+                len: pattern.len(),
+            },
             next_group_num: 1,
+            group_names: vec![None],
+            flags,
+            depth: 0,
+            back_refs: Vec::new(),
+            named_refs: Vec::new(),
+        }
+    }
+
+    /// 跳过 x 模式下的空白和注释之后再 peek，只用在分组、分支和量词之间
+    fn peek(&mut self) -> Option<&char> {
+        while self.flags.verbose {
+            match self.chars.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.chars.next();
+                }
+                Some('#') => while self.chars.next_if(|c| *c != '\n').is_some() {},
+                _ => break,
+            }
+        }
+        self.chars.peek()
+    }
+
+    /// 从 start 到当前位置的语法出错
+    fn error(&mut self, kind: ErrorKind, start: usize) -> ParseError {
+        let end = self.chars.pos();
+        ParseError::new(kind, start..end, self.pattern)
+    }
+
+    /// 进入一层分组或字符类，start 是它开头的位置
+    fn nest(&mut self, start: usize) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > NEST_LIMIT {
+            return Err(self.error(ErrorKind::NestTooDeep, start));
         }
+        Ok(())
     }
 
     pub fn next_group_num(&mut self) -> usize {
         let group_num = self.next_group_num;
         self.next_group_num += 1;
-        self.num_stack.push(group_num);
+        self.group_names.push(None);
         group_num
     }
-    pub fn current_group_num(&mut self) -> Result<usize, ParseError> {
-        if let Some(num) = self.num_stack.pop() {
-            Ok(num)
-        } else {
-            return Err(ParseError::GroupNumMissError);
+
+    /// 给刚分配组号的分组起名字
+    fn name_group(&mut self, group_num: usize, name: String) -> Result<(), ErrorKind> {
+        if self.group_names.iter().flatten().any(|n| *n == name) {
+            return Err(ErrorKind::DuplicateGroupName(name));
         }
+        self.group_names[group_num] = Some(name);
+        Ok(())
     }
 
-    pub fn compile(mut self) -> Result<Vec<Inst>, ParseError> {
-        let instrs = self.parse_expr()?;
-        self.instrs.extend(instrs);
-        self.instrs.push(Inst::Match);
-        Ok(self.instrs)
+    fn group_num_of(&self, name: &str) -> Result<usize, ErrorKind> {
+        self.group_names
+            .iter()
+            .position(|n| n.as_deref() == Some(name))
+            .ok_or_else(|| ErrorKind::UnknownGroupName(name.to_string()))
     }
 
-    fn parse_expr(&mut self) -> Result<Vec<Inst>, ParseError> {
-        let mut instrs = vec![];
-        while let Some(_) = self.chars.peek() {
-            instrs.extend(self.parse_term()?);
+    /// 解析整个模式串，得到语法树
+    pub fn parse(mut self) -> Result<Ast, ParseError> {
+        let mut ast = self.parse_expr()?;
+        // 可以引用后面才出现的分组，但不能引用不存在的
+        if let Some((group, span)) = self
+            .back_refs
+            .iter()
+            .find(|(group, _)| *group >= self.group_names.len())
+        {
+            return Err(ParseError::new(
+                ErrorKind::UnknownGroup(*group),
+                span.clone(),
+                self.pattern,
+            ));
+        }
+        let mut groups = Vec::with_capacity(self.named_refs.len());
+        for (name, span) in &self.named_refs {
+            let group = self
+                .group_num_of(name)
+                .map_err(|kind| ParseError::new(kind, span.clone(), self.pattern))?;
+            groups.push(group);
         }
-        Ok(instrs)
+        ast.resolve_named_refs(&mut groups.into_iter());
+        Ok(ast)
     }
 
-    fn parse_term(&mut self) -> Result<Vec<Inst>, ParseError> {
-        let block = if let Some('(') = self.chars.peek() {
-            self.chars.next(); // 实际消耗 '('
+    /// 解析并编译成最多 size_limit 条指令的 `Inst` 程序
+    pub fn compile(self, size_limit: usize) -> Result<Program, ParseError> {
+        let pattern = self.pattern;
+        Compiler::compile(&self.parse()?, pattern, size_limit)
+    }
 
-            // 先插入分组开始的指令
-            let num = self.next_group_num();
-            let mut group_instrs = vec![];
-            group_instrs.push(Inst::GroupBegin(num));
-
-            // let mut real_split: bool = false;
-            let mut branches: Vec<Vec<Inst>> = vec![];
-
-            loop {
-                match self.chars.peek() {
-                    None => return Err(ParseError::UnclosedGroup),
-                    Some('|') => {
-                        self.chars.next();
-                        
-                        let mut new_branch = vec![];
-                        loop {
-                            match self.chars.peek() {
-                                Some('|') | Some(')') | None => break,
-                                Some(_) => new_branch.extend(self.parse_term()?),
-                            }
-                        }
-                        branches.push(new_branch);
-                    }
-                    Some(')') => {
-                        self.chars.next();
-                        if branches.len() <= 1 {
-                            group_instrs.extend(branches.pop().unwrap_or(vec![]));
-                        } else {
-                            let mut split_code = branches.pop().unwrap();
-                            while let Some(mut new_branch) = branches.pop() {
-                                let jump = Self::emit_jump_forward(&split_code);
-                                new_branch.push(jump);
-                                split_code = Self::emit_split_code(new_branch, split_code);
-                            }
-                            group_instrs.extend(split_code);
-                        }
-                        // 插入分组结束的指令
-                        let num = self.current_group_num()?;
-                        group_instrs.push(Inst::GroupEnd(num));
-                        break;
-                    }
-                    Some(_) => {
-                        let mut new_branch = vec![];
-                        loop {
-                            match self.chars.peek() {
-                                Some('|') | Some(')') | None => break,
-                                Some(_) => new_branch.extend(self.parse_term()?),
-                            }
-                        }
-                        branches.push(new_branch);
-                    }
-                }
+    fn parse_expr(&mut self) -> Result<Ast, ParseError> {
+        let ast = self.parse_alternation()?;
+        // 顶层剩下的只可能是多出来的 ')'
+        let start = self.chars.pos();
+        match self.chars.next() {
+            Some(c) => Err(self.error(ErrorKind::UnexpectedMetaChar(c), start)),
+            None => Ok(ast),
+        }
+    }
+
+    /// 用 '|' 分隔的若干分支，优先级最低。分支可以为空，例如 `(a|)`、`|b`。
+    /// 遇到 ')' 或模式串结束时返回，')' 留给调用方处理
+    fn parse_alternation(&mut self) -> Result<Ast, ParseError> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.peek() == Some(&'|') {
+            self.chars.next();
+            branches.push(self.parse_concat()?);
+        }
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap_or(Ast::Empty))
+        } else {
+            Ok(Ast::Alternation(branches))
+        }
+    }
+
+    /// 一个分支：依次连接的若干项
+    fn parse_concat(&mut self) -> Result<Ast, ParseError> {
+        let mut asts = vec![];
+        while !matches!(self.peek(), Some('|' | ')') | None) {
+            match self.parse_term()? {
+                // (?i) 这样的标志分组不留在树里
+                Ast::Empty => {}
+                ast => asts.push(ast),
             }
+        }
+        match asts.len() {
+            0 => Ok(Ast::Empty),
+            1 => Ok(asts.pop().unwrap_or(Ast::Empty)),
+            _ => Ok(Ast::Concat(asts)),
+        }
+    }
 
-            group_instrs
+    /// 一个分组或原子，以及跟在后面的量词
+    fn parse_term(&mut self) -> Result<Ast, ParseError> {
+        let start = self.chars.pos();
+        let ast = if let Some('(') = self.peek() {
+            self.chars.next(); // 实际消耗 '('
+            match self.parse_group(start)? {
+                Some(ast) => ast,
+                // (?i) 没有内容，后面也不能接量词
+                None => return Ok(Ast::Empty),
+            }
         } else {
-            // parse like: 'a*','a+' or 'a?'
             self.parse_atom()?
         };
+        self.parse_repetition(ast, start)
+    }
 
-        let block = match self.chars.peek() {
-            Some('*') => {
-                self.chars.next();
-                Self::emit_zero_or_more_code(block)
+    /// `(` 之后的分组，start 是 '(' 的位置。标志分组 `(?i)` 只修改标志，返回 None
+    fn parse_group(&mut self, start: usize) -> Result<Option<Ast>, ParseError> {
+        let syntax = self.parse_group_kind(start)?;
+
+        // 分组里修改的标志在分组结束后恢复
+        let outer_flags = self.flags;
+        let kind = match syntax {
+            GroupSyntax::SetFlags(flags) => {
+                self.flags = flags;
+                return Ok(None);
             }
-            Some('+') => {
-                self.chars.next();
-                Self::emit_one_or_more_code(block)
+            GroupSyntax::Scoped(flags) => {
+                self.flags = flags;
+                GroupKind::NonCapture
             }
-            Some('?') => {
-                self.chars.next();
-                Self::emit_zero_or_one_code(block)
+            // 组号在读到 '(' 时分配，保证外层分组的组号更小
+            GroupSyntax::Capture(name) => {
+                let index = self.next_group_num();
+                if let Some(name) = &name {
+                    self.name_group(index, name.clone())
+                        .map_err(|kind| self.error(kind, start))?;
+                }
+                GroupKind::Capture { index, name }
             }
-            Some('{') => {
-                self.chars.next();
+            GroupSyntax::NonCapture => GroupKind::NonCapture,
+            GroupSyntax::Atomic => GroupKind::Atomic,
+            GroupSyntax::LookAhead { negate } => GroupKind::LookAhead { negate },
+            GroupSyntax::LookBehind { negate } => GroupKind::LookBehind { negate },
+        };
 
-                let mut digit_buffer = vec![];
-                let mut min = 1;
-                let mut max = 1;
-                loop {
-                    match self.chars.peek() {
-                        Some(d @ ('0'..='9' | ',')) => {
-                            digit_buffer.push(*d);
-                            self.chars.next();
-                        }
-                        Some('}') => {
-                            self.chars.next();
-                            let digit_text: String = digit_buffer.iter().collect();
-                            let mut digits: Vec<&str> = digit_text.split(",").collect();
-
-                            eprintln!(">>> {:?}", digits);
-                            if let Some(&d1) = digits.get(0) {
-                                eprintln!("-------> {d1}");
-                                min = d1.parse::<usize>().map_err(|err| {
-                                    ParseError::InvalidQuantifier(format!(
-                                        "解析数字失败 '{}': {}",
-                                        d1, err
-                                    ))
-                                })?;
-                            } else {
-                                return Err(ParseError::InvalidQuantifier(format!(
-                                    "非法的量词格式"
-                                )));
-                            }
+        self.nest(start)?;
+        let ast = self.parse_alternation()?;
+        self.depth -= 1;
+        if self.chars.next() != Some(')') {
+            // 指向没有配对的 '('
+            let kind = ErrorKind::UnclosedGroup;
+            return Err(ParseError::new(kind, start..start + 1, self.pattern));
+        }
+        if matches!(kind, GroupKind::LookBehind { .. }) && ast.length_bounds().is_none() {
+            return Err(self.error(ErrorKind::UnboundedLookBehind, start));
+        }
+        self.flags = outer_flags;
+        Ok(Some(Ast::Group(Group {
+            kind,
+            ast: Box::new(ast),
+            span: start..self.chars.pos(),
+        })))
+    }
 
-                            match digits.get(1) {
-                                None => max = min,
-                                Some(&d2) => {
-                                    if d2.is_empty() {
-                                        max = usize::MAX;
-                                        eprintln!("-------> {max}");
-                                    } else {
-                                        max = d2.parse::<usize>().map_err(|err| {
-                                            ParseError::InvalidQuantifier(format!(
-                                                "解析数字失败 '{}': {}",
-                                                d2, err
-                                            ))
-                                        })?;
-                                    }
-                                }
-                            }
+    /// 可选的量词：'*'、'+'、'?' 或 `{n}`、`{n,}`、`{n,m}`，后面可以再跟 '?' 或 '+'。
+    /// start 是被重复的部分开头的位置
+    fn parse_repetition(&mut self, ast: Ast, start: usize) -> Result<Ast, ParseError> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                let brace = self.chars.pos();
+                self.chars.next();
+                let (min, max) = self.parse_counted(brace)?;
+                let greed = self.parse_greed();
+                return Ok(Ast::Repetition(Repetition {
+                    ast: Box::new(ast),
+                    min,
+                    max,
+                    greed,
+                    span: start..self.chars.pos(),
+                }));
+            }
+            _ => return Ok(ast),
+        };
+        self.chars.next();
+        let greed = self.parse_greed();
+        Ok(Ast::Repetition(Repetition {
+            ast: Box::new(ast),
+            min,
+            max,
+            greed,
+            span: start..self.chars.pos(),
+        }))
+    }
 
-                            break;
-                        }
-                        Some(_) | None => {
-                            return Err(ParseError::InvalidQuantifier(format!("解析量词失败")))
-                        }
-                    }
+    /// `{` 之后的 `n}`、`n,}` 或 `n,m}`，start 是 '{' 的位置
+    fn parse_counted(&mut self, start: usize) -> Result<(usize, Option<usize>), ParseError> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some('}') => break,
+                Some(c @ ('0'..='9' | ',')) => text.push(c),
+                Some(c) => {
+                    text.push(c);
+                    return Err(self.error(ErrorKind::InvalidQuantifier(text), start));
                 }
+                None => return Err(self.error(ErrorKind::InvalidQuantifier(text), start)),
+            }
+        }
 
-                let mut repeat_block = vec![];
+        let parse_num = |digits: &str| {
+            digits
+                .parse::<usize>()
+                .map_err(|_| ErrorKind::InvalidQuantifier(text.clone()))
+        };
+        let bounds = match text.split_once(',') {
+            None => parse_num(&text).map(|n| (n, Some(n))),
+            Some((min, "")) => parse_num(min).map(|min| (min, None)),
+            Some((min, max)) => parse_num(min).and_then(|min| Ok((min, Some(parse_num(max)?)))),
+        };
+        match bounds {
+            Ok((min, Some(max))) if min > max => {
+                Err(self.error(ErrorKind::InvalidQuantifier(text), start))
+            }
+            Ok(bounds) => Ok(bounds),
+            Err(kind) => Err(self.error(kind, start)),
+        }
+    }
 
-                if min > max {
-                    return Err(ParseError::InvalidQuantifier(format!("解析量词失败")));
+    fn parse_atom(&mut self) -> Result<Ast, ParseError> {
+        let start = self.chars.pos();
+        let ast = match self.chars.next() {
+            Some('.') => Ast::Dot {
+                newline: self.flags.dot_all,
+            },
+            Some('\\') => match self.chars.next() {
+                Some('d') => Ast::Digit,
+                Some('w') => Ast::Word,
+                Some('b') => Ast::Assertion(Assertion::WordBoundary),
+                Some('B') => Ast::Assertion(Assertion::NotWordBoundary),
+                // 不受多行模式影响，总是针对整个输入
+                Some('A') => Ast::Assertion(Assertion::Start),
+                Some('z') => Ast::Assertion(Assertion::End),
+                Some('Z') => Ast::Assertion(Assertion::EndBeforeNewline),
+                Some(c @ ('D' | 'W' | 's' | 'S')) => Ast::Class(self.escape_class(c)),
+                Some(p @ ('p' | 'P')) => {
+                    let property = self.parse_property(start)?;
+                    Ast::Class(self.class_item(property, p == 'P'))
                 }
-
-                for _ in 1..=min {
-                    repeat_block.extend_from_slice(&block);
+                Some(d @ '1'..='9') => {
+                    // 向前引用 \1 \2
+                    let group = d.to_digit(10).unwrap_or_default() as usize;
+                    self.back_refs.push((group, start..self.chars.pos()));
+                    self.back_ref(group)
                 }
-                if max == usize::MAX {
-                    // >= min
-                    repeat_block.extend(Self::emit_zero_or_more_code(block))
-                } else if max != min {
-                    // repeat min - max times
-                    let at_most_once = Self::emit_zero_or_one_code(block);
-                    for _ in 1..=(max - min) {
-                        repeat_block.extend_from_slice(&at_most_once);
+                Some('k') => {
+                    // 按名字引用 \k<name>
+                    if self.chars.next_if_eq(&'<').is_none() {
+                        return Err(self.error(ErrorKind::UnknownEscape('k'), start));
                     }
+                    // 组号要等解析完才能确定，先记作第 0 组
+                    let name = self.parse_group_name(start)?;
+                    self.named_refs.push((name, start..self.chars.pos()));
+                    self.back_ref(0)
                 }
-
-                repeat_block
+                Some(e) => match self.parse_char_escape(e, start)? {
+                    Some(c) => self.literal(c),
+                    None => return Err(self.error(ErrorKind::UnknownEscape(e), start)),
+                },
+                None => return Err(self.error(ErrorKind::IncompletedEscape, start)),
+            },
+            Some('[') => Ast::Class(self.parse_class(start)?),
+            Some('^') if self.flags.multi_line => Ast::Assertion(Assertion::StartLine),
+            Some('^') => Ast::Assertion(Assertion::Start),
+            Some('$') if self.flags.multi_line => Ast::Assertion(Assertion::EndLine),
+            Some('$') => Ast::Assertion(Assertion::End),
+            // 其余的元字符出现在这里说明前面没有可以修饰的内容，或者括号不配对
+            Some(c @ ('*' | '+' | '?' | '{' | '(' | ')' | '|')) => {
+                return Err(self.error(ErrorKind::UnexpectedMetaChar(c), start))
             }
-            Some(_) | None => block,
+            Some(ch) => self.literal(ch),
+            None => Ast::Empty,
         };
+        Ok(ast)
+    }
 
-        Ok(block)
+    fn literal(&self, ch: char) -> Ast {
+        Ast::Literal {
+            ch,
+            ignore_case: self.flags.case_insensitive,
+        }
     }
 
-    fn parse_atom(&mut self) -> Result<Vec<Inst>, ParseError> {
-        let mut atom_instrs = vec![];
+    fn back_ref(&self, group: usize) -> Ast {
+        Ast::BackRef {
+            group,
+            ignore_case: self.flags.case_insensitive,
+        }
+    }
 
-        match self.chars.next() {
-            Some('.') => atom_instrs.push(Inst::AnyChar),
-            Some(
-                ch @ ('a'..='z'
-                | 'A'..='Z'
-                | '0'..='9'
-                | ','
-                | '<'
-                | '>'
-                | ':'
-                | ' '
-                | '-'
-                | '_'
-                | '"'
-                | '\''),
-            ) => {
-                atom_instrs.push(Inst::Char(ch));
+    /// `[a-z&&[^aeiou]]` 这样的集合运算优先级相同，从左往右计算，最后再处理开头的 '^'。
+    /// 和 POSIX 一样，紧跟在 `[` 或 `[^` 后面的 `]` 是普通字符，所以 `[]` 和 `[^]` 都没有闭合。
+    /// start 是 '[' 的位置
+    fn parse_class(&mut self, start: usize) -> Result<ClassSet, ParseError> {
+        self.nest(start)?;
+        let negated = self.chars.next_if_eq(&'^').is_some();
+        let (mut set, mut op) = self.parse_class_operand(start, true)?;
+        while let Some(current) = op {
+            let (rhs, next) = self.parse_class_operand(start, false)?;
+            match current {
+                SetOp::Intersection => set.intersect(&rhs),
+                SetOp::Difference => set.difference(&rhs),
+                SetOp::SymmetricDifference => set.symmetric_difference(&rhs),
             }
-            Some('\\') => match self.chars.next() {
-                Some('d') => atom_instrs.push(Inst::Digit),
-                Some('w') => atom_instrs.push(Inst::MetaChar),
-                Some('\\') => atom_instrs.push(Inst::Char('\\')),
-                Some(d @ '1'..='9') => {
-                    // 向前引用 \1 \2
-                    atom_instrs.push(Inst::Ref(d.to_digit(10).unwrap() as usize))
+            op = next;
+        }
+        if negated {
+            set.negate();
+        }
+        self.depth -= 1;
+        Ok(set)
+    }
+
+    /// 集合运算符之间的一段，里面的各项取并集。
+    /// 返回这一段的字符集，以及结束这一段的运算符，读到 `]` 时运算符是 None。
+    /// leading 表示这是类的第一段，这时开头的 `]` 是普通字符
+    fn parse_class_operand(
+        &mut self,
+        class_start: usize,
+        leading: bool,
+    ) -> Result<(ClassSet, Option<SetOp>), ParseError> {
+        let operand_start = self.chars.pos();
+        // 直接列出的字符和范围，最后统一处理大小写
+        let mut set = ClassSet::new();
+        // 转义、POSIX 类和嵌套的类，加入前已经各自处理过大小写和取反
+        let mut items = ClassSet::new();
+        let op = loop {
+            let start = self.chars.pos();
+            let op = match self.chars.next() {
+                Some(']') if !(leading && start == operand_start) => break None,
+                Some('\\') => match self.chars.next() {
+                    Some(c @ ('d' | 'D' | 'w' | 'W' | 's' | 'S')) => {
+                        items.union(&self.escape_class(c));
+                        None
+                    }
+                    Some(p @ ('p' | 'P')) => {
+                        let property = self.parse_property(start)?;
+                        items.union(&self.class_item(property, p == 'P'));
+                        None
+                    }
+                    Some(e) => match self.parse_char_escape(e, start)? {
+                        Some(c) => self.parse_class_range(c, start, &mut set)?,
+                        None => return Err(self.error(ErrorKind::UnknownEscape(e), start)),
+                    },
+                    None => return Err(self.error(ErrorKind::IncompletedEscape, start)),
+                },
+                Some('[') if self.chars.next_if_eq(&':').is_some() => {
+                    let (posix, negated) = self.parse_posix_class(start)?;
+                    items.union(&self.class_item(posix, negated));
+                    None
                 }
-                Some(e) => return Err(ParseError::UnknownEscape(e)),
-                None => return Err(ParseError::IncompletedEscape),
-            },
-            Some('[') => {
-                let mut set = HashSet::new();
-                let mut negated = false;
-                if let Some('^') = self.chars.peek() {
-                    negated = true;
-                    self.chars.next();
+                Some('[') => {
+                    items.union(&self.parse_class(start)?);
+                    None
                 }
-                loop {
-                    match self.chars.next() {
-                        Some(']') => {
-                            atom_instrs.push(Inst::CharClass {
-                                negated,
-                                chars: set,
-                            });
-                            break;
-                        }
-                        Some('\\') => match self.chars.next() {
-                            Some('d') => set.extend(('0'..='9').into_iter()),
-                            Some('w') => {
-                                set.insert('_');
-                                set.extend(('0'..='9').into_iter());
-                                set.extend(('a'..='z').into_iter());
-                                set.extend(('A'..='Z').into_iter());
-                            }
-                            Some(c) => return Err(ParseError::UnknownEscape(c)),
-                            None => return Err(ParseError::IncompletedEscape),
-                        },
-                        Some(start) if start.is_ascii_alphanumeric() => {
-                            if self.chars.next_if_eq(&'-').is_none() {
-                                set.insert(start);
-                            } else if let Some(end) =
-                                self.chars.next_if(|c| c.is_ascii_alphanumeric())
-                            {
-                                set.extend(Self::translate_range(start, end));
-                            } else {
-                                set.insert('-');
-                            }
-                        }
-                        Some(ch) => {
-                            set.insert(ch);
-                        }
-                        None => return Err(ParseError::UnclosedCharClass),
-                    }
+                Some('&') if self.chars.next_if_eq(&'&').is_some() => Some(SetOp::Intersection),
+                Some('-') if self.chars.next_if_eq(&'-').is_some() => Some(SetOp::Difference),
+                Some('~') if self.chars.next_if_eq(&'~').is_some() => {
+                    Some(SetOp::SymmetricDifference)
                 }
+                Some(c) => self.parse_class_range(c, start, &mut set)?,
+                None => return Err(self.error(ErrorKind::UnclosedCharClass, class_start)),
+            };
+            if op.is_some() {
+                break op;
             }
-            Some('^') => atom_instrs.push(Inst::Start),
-            Some('$') => {
-                atom_instrs.push(Inst::End);
-                if self.chars.peek().is_some() {
-                    return Err(ParseError::MisplacedAnchor);
-                }
+        };
+
+        if self.flags.case_insensitive {
+            set.case_fold();
+        }
+        set.union(&items);
+        Ok((set, op))
+    }
+
+    /// 读到字符类里的一个字符 start 之后，看它是不是 `a-z` 这样的范围的开头，
+    /// start_pos 是这个字符的位置。`a--b` 里的 `--` 是求差集，要把运算符交给调用方
+    fn parse_class_range(
+        &mut self,
+        start: char,
+        start_pos: usize,
+        set: &mut ClassSet,
+    ) -> Result<Option<SetOp>, ParseError> {
+        if self.chars.next_if_eq(&'-').is_none() {
+            set.push_char(start);
+            return Ok(None);
+        }
+        let end = match self.chars.next_if(|c| *c != ']') {
+            // `[a-]` 里的 '-' 是普通字符
+            None => {
+                set.push_char(start);
+                set.push_char('-');
+                return Ok(None);
             }
-            Some(c) => {
-                eprintln!("未支持的字符: {}", c);
-                todo!()
+            Some('-') => {
+                set.push_char(start);
+                return Ok(Some(SetOp::Difference));
             }
-            None => todo!(),
+            Some('\\') => {
+                let escape_start = self.chars.pos() - 1;
+                match self.chars.next() {
+                    Some(e) => match self.parse_char_escape(e, escape_start)? {
+                        Some(c) => c,
+                        None => {
+                            let kind = ErrorKind::UnknownEscape(e);
+                            return Err(self.error(kind, escape_start));
+                        }
+                    },
+                    None => return Err(self.error(ErrorKind::IncompletedEscape, escape_start)),
+                }
+            }
+            Some(end) => end,
+        };
+        if start > end {
+            return Err(self.error(ErrorKind::InvalidRange(start, end), start_pos));
         }
+        set.push(start, end);
+        Ok(None)
+    }
 
-        Ok(atom_instrs)
+    /// `\d` `\D` `\w` `\W` `\s` `\S` 对应的字符类，大写的是取反
+    fn escape_class(&self, c: char) -> ClassSet {
+        let set = ClassSet::from_escape(c.to_ascii_lowercase()).unwrap_or_default();
+        self.class_item(set, c.is_ascii_uppercase())
     }
 
-    fn pc(&self) -> usize {
-        self.instrs.len()
+    /// 先按当前标志处理大小写，再取反，`(?i)[^a]` 不能匹配 'A'
+    fn class_item(&self, mut set: ClassSet, negated: bool) -> ClassSet {
+        if self.flags.case_insensitive {
+            set.case_fold();
+        }
+        if negated {
+            set.negate();
+        }
+        set
     }
 
-    fn calc_jump_offset<T>(base_pc: T, target_pc: T) -> isize
-    where
-        T: Add<Output = T> + Sub<Output = T> + Into<isize>,
-    {
-        let base: isize = base_pc.into();
-        let target: isize = target_pc.into();
-        target - (base)
+    /// `\p` 或 `\P` 之后的属性名：单个字母 `\pL`，或者 `\p{Greek}` 这种。
+    /// start 是 '\\' 的位置
+    fn parse_property(&mut self, start: usize) -> Result<ClassSet, ParseError> {
+        let name = match self.chars.next() {
+            Some('{') => {
+                let mut name = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some('}') => break name,
+                        Some(c) => name.push(c),
+                        None => return Err(self.error(ErrorKind::UnknownProperty(name), start)),
+                    }
+                }
+            }
+            Some(c) => c.to_string(),
+            None => return Err(self.error(ErrorKind::IncompletedEscape, start)),
+        };
+        match unicode::property(&name) {
+            Some(ranges) => Ok(ClassSet::from_ranges(ranges)),
+            None => Err(self.error(ErrorKind::UnknownProperty(name), start)),
+        }
     }
 
-    fn emit_jump_forward(insts: &Vec<Inst>) -> Inst {
-        let target = insts.len() + 1;
-        let base = 0;
-        let offset = Self::calc_jump_offset(base as isize, target as isize);
-        Inst::Jump(offset)
+    /// `[:` 之后的 POSIX 字符类名，以 `:]` 结束，`[:^alpha:]` 表示取反。start 是 '[' 的位置
+    fn parse_posix_class(&mut self, start: usize) -> Result<(ClassSet, bool), ParseError> {
+        let negated = self.chars.next_if_eq(&'^').is_some();
+        let mut name = String::new();
+        while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphabetic()) {
+            name.push(c);
+        }
+        match (ClassSet::posix(&name), self.chars.next(), self.chars.next()) {
+            (Some(set), Some(':'), Some(']')) => Ok((set, negated)),
+            _ => Err(self.error(ErrorKind::InvalidPosixClass(name), start)),
+        }
     }
 
-    fn emit_jump_backward(insts: &Vec<Inst>) -> Inst {
-        let target = -1;
-        let base = insts.len();
-        let offset = Self::calc_jump_offset(base as isize, target as isize);
-        Inst::Jump(offset)
+    /// 表示单个字符的转义，括号内外通用。e 是 '\\' 后面的字符，start 是 '\\' 的位置，
+    /// 不是这类转义时返回 None
+    fn parse_char_escape(&mut self, e: char, start: usize) -> Result<Option<char>, ParseError> {
+        let ch = match e {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'f' => '\x0C',
+            'v' => '\x0B',
+            'a' => '\x07',
+            'e' => '\x1B',
+            '0' => '\0',
+            // \cX：Ctrl+X
+            'c' => match self.chars.next() {
+                Some(x) if x.is_ascii_alphabetic() => (x.to_ascii_uppercase() as u8 - b'@') as char,
+                _ => return Err(self.error(ErrorKind::UnknownEscape('c'), start)),
+            },
+            // \xHH、\x{H..}、\u{H..}
+            'x' | 'u' => {
+                let braced = self.chars.next_if_eq(&'{').is_some();
+                let digits: String = if braced {
+                    let mut digits = String::new();
+                    loop {
+                        match self.chars.next() {
+                            Some('}') => break,
+                            Some(c) => digits.push(c),
+                            None => {
+                                return Err(self.error(ErrorKind::InvalidCodePoint(digits), start))
+                            }
+                        }
+                    }
+                    digits
+                } else if e == 'x' {
+                    self.chars.by_ref().take(2).collect()
+                } else {
+                    return Err(self.error(ErrorKind::UnknownEscape('u'), start));
+                };
+                let valid = (if braced { 1..=6 } else { 2..=2 }).contains(&digits.len())
+                    && digits.chars().all(|c| c.is_ascii_hexdigit());
+                match u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .filter(|_| valid)
+                    .and_then(char::from_u32)
+                {
+                    Some(c) => c,
+                    None => return Err(self.error(ErrorKind::InvalidCodePoint(digits), start)),
+                }
+            }
+            // 转义的标点都按原样匹配，x 模式下要写出空白也只能转义
+            c if c.is_ascii_punctuation() || c == ' ' => c,
+            _ => return Ok(None),
+        };
+        Ok(Some(ch))
     }
 
-    fn emit_split_code(branch1: Vec<Inst>, branch2: Vec<Inst>) -> Vec<Inst> {
-        let split_index = 0;
-        let branch1_len = branch1.len();
-        let branch2_index = split_index + branch1_len + 1;
+    /// 在 `(` 之后读取 `?` 开头的分组语法，start 是 '(' 的位置
+    fn parse_group_kind(&mut self, start: usize) -> Result<GroupSyntax, ParseError> {
+        if self.chars.next_if_eq(&'?').is_none() {
+            return Ok(GroupSyntax::Capture(None));
+        }
+        let kind = match self.chars.next() {
+            Some(':') => GroupSyntax::NonCapture,
+            Some('>') => GroupSyntax::Atomic,
+            Some('=') => GroupSyntax::LookAhead { negate: false },
+            Some('!') => GroupSyntax::LookAhead { negate: true },
+            Some(c @ ('i' | 'm' | 's' | 'x' | '-')) => {
+                let mut flags = self.flags;
+                let mut on = true;
+                let mut flag = c;
+                loop {
+                    if flag == '-' {
+                        on = false;
+                    } else {
+                        flags
+                            .set(flag, on)
+                            .map_err(|kind| self.error(kind, start))?;
+                    }
+                    match self.chars.next() {
+                        Some(')') => break GroupSyntax::SetFlags(flags),
+                        Some(':') => break GroupSyntax::Scoped(flags),
+                        Some(next) => flag = next,
+                        None => return Err(self.error(ErrorKind::UnclosedGroup, start)),
+                    }
+                }
+            }
+            Some('P') if self.chars.next_if_eq(&'<').is_some() => {
+                GroupSyntax::Capture(Some(self.parse_group_name(start)?))
+            }
+            Some('<') => match self.chars.peek() {
+                Some('=') => {
+                    self.chars.next();
+                    GroupSyntax::LookBehind { negate: false }
+                }
+                Some('!') => {
+                    self.chars.next();
+                    GroupSyntax::LookBehind { negate: true }
+                }
+                _ => GroupSyntax::Capture(Some(self.parse_group_name(start)?)),
+            },
+            Some(c) => return Err(self.error(ErrorKind::UnknownGroupFlag(c), start)),
+            None => return Err(self.error(ErrorKind::UnclosedGroup, start)),
+        };
+        Ok(kind)
+    }
 
-        let split_code = Inst::Split(
-            1,
-            Self::calc_jump_offset(split_index as isize, branch2_index as isize),
-        );
+    fn parse_greed(&mut self) -> Greed {
+        if self.chars.next_if_eq(&'?').is_some() {
+            Greed::Lazy
+        } else if self.chars.next_if_eq(&'+').is_some() {
+            Greed::Possessive
+        } else {
+            Greed::Greedy
+        }
+    }
 
-        let mut target_instrs: Vec<Inst> = Vec::new();
-        target_instrs.push(split_code);
-        target_instrs.extend(branch1.into_iter());
-        target_instrs.extend(branch2.into_iter());
+    /// 读取 `<` 之后的分组名，直到 `>`。start 是 '(' 或 '\\' 的位置
+    fn parse_group_name(&mut self, start: usize) -> Result<String, ParseError> {
+        let mut name = String::new();
+        loop {
+            match self.chars.next() {
+                Some('>') => break,
+                Some(c) if c.is_alphanumeric() || c == '_' => name.push(c),
+                Some(c) => {
+                    name.push(c);
+                    return Err(self.error(ErrorKind::InvalidGroupName(name), start));
+                }
+                None => return Err(self.error(ErrorKind::InvalidGroupName(name), start)),
+            }
+        }
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(self.error(ErrorKind::InvalidGroupName(name), start));
+        }
+        Ok(name)
+    }
+}
 
-        target_instrs
+#[cfg(test)]
+mod tests {
+    use crate::regex::parser::*;
+
+    fn parse_error(pattern: &str) -> ParseError {
+        Parser::new(pattern).parse().unwrap_err()
     }
 
-    fn emit_zero_or_more_code(block: Vec<Inst>) -> Vec<Inst> {
-        let mut branch1 = block;
-        let branch2 = vec![];
-        branch1.push(Self::emit_jump_backward(&branch1));
-        Self::emit_split_code(branch1, branch2)
+    #[test]
+    fn test_error_span() {
+        let err = parse_error("a[bc");
+        assert_eq!(err.kind, ErrorKind::UnclosedCharClass);
+        assert_eq!(err.span, 1..4);
+        assert_eq!(parse_error(r"ab\q").span, 2..4);
+        assert_eq!(parse_error("a(b(c)").span, 1..2);
+        assert_eq!(parse_error("a{3,1}").span, 1..6);
+        assert_eq!(parse_error("[z-a]").span, 1..4);
+        // 开头的 ']' 是普通字符，这两个类都没有闭合
+        assert_eq!(parse_error("a[]").kind, ErrorKind::UnclosedCharClass);
+        assert_eq!(parse_error("a[^]").span, 1..4);
+        assert_eq!(parse_error(r"(?<=a+)b").span, 0..7);
+        // 按字节计算
+        assert_eq!(parse_error("中文)").span, 6..7);
     }
 
-    fn emit_one_or_more_code(block: Vec<Inst>) -> Vec<Inst> {
-        let mut res = vec![];
-        res.extend_from_slice(&block);
-        res.extend(Self::emit_zero_or_more_code(block));
-        res
+    #[test]
+    fn test_error_display() {
+        let err = parse_error("a[bc");
+        assert_eq!(err.to_string(), "unclosed character class, missing ']'");
+        assert_eq!(
+            format!("{:#}", err),
+            "unclosed character class, missing ']'\n    a[bc\n     ^~~"
+        );
+        // 只列出出错的那一行
+        let err = Parser::with_flags(
+            "a\n  (?<1x>b)",
+            Flags {
+                verbose: true,
+                ..Flags::default()
+            },
+        )
+        .parse()
+        .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "invalid group name: '1x'\n      (?<1x>b)\n      ^~~~~~"
+        );
+        // 宽字符占两列
+        let err = parse_error("中文(?<名字");
+        assert_eq!(
+            format!("{:#}", err),
+            "invalid group name: '名字'\n    中文(?<名字\n        ^~~~~~~"
+        );
     }
 
-    fn emit_zero_or_one_code(block: Vec<Inst>) -> Vec<Inst> {
-        let branch1 = block;
-        let branch2 = vec![];
-        Self::emit_split_code(branch1, branch2)
+    #[test]
+    fn test_error_message() {
+        let err = parse_error("a{3,1}");
+        assert_eq!(err.code(), "invalid_quantifier");
+        assert_eq!(err.kind, ErrorKind::InvalidQuantifier("3,1".to_string()));
+        assert_eq!(err.kind.message(Lang::English), "invalid repetition count");
+        assert_eq!(err.kind.message(Lang::Chinese), "非法的量词数字");
+
+        let err = parse_error(r"(?<a>x)\k<b>");
+        assert_eq!(err.code(), "unknown_group_name");
+        assert_eq!(err.kind.message(Lang::Chinese), "未定义的分组名: 'b'");
     }
 
-    fn translate_range(start: char, end: char) -> impl IntoIterator<Item = char> {
-        let is_range = (start as usize <= end as usize)
-            && (start.is_ascii_digit() && end.is_ascii_digit()
-                || start.is_ascii_lowercase() && end.is_ascii_lowercase()
-                || start.is_ascii_uppercase() && end.is_ascii_uppercase());
+    #[test]
+    fn test_unknown_group() {
+        let err = parse_error(r"\5");
+        assert_eq!(err.kind, ErrorKind::UnknownGroup(5));
+        assert_eq!(err.code(), "unknown_group");
+        assert_eq!(err.span, 0..2);
+        assert_eq!(err.to_string(), r"reference to undefined group: '\5'");
+        assert_eq!(parse_error(r"(a)x\2").span, 4..6);
+        assert_eq!(parse_error(r"(?:a)\1").kind, ErrorKind::UnknownGroup(1));
+        // 引用后面的分组是可以的
+        assert!(Parser::new(r"(a)\2(b)").parse().is_ok());
+    }
 
-        if is_range {
-            return (start..=end).collect::<Vec<char>>().into_iter();
-        } else {
-            return vec![start, '-', end].into_iter();
-        }
+    #[test]
+    fn test_unknown_group_name_span() {
+        let err = parse_error(r"(?<a>x)y\k<b>");
+        assert_eq!(err.kind, ErrorKind::UnknownGroupName("b".to_string()));
+        assert_eq!(err.span, 8..13);
+        // 和按组号的引用一样，可以引用后面才定义的名字
+        let ast = Parser::new(r"\k<b>(?<a>x)(?<b>y)").parse().unwrap();
+        let Ast::Concat(asts) = ast else {
+            panic!("不是 Concat: {ast:?}");
+        };
+        assert_eq!(
+            asts[0],
+            Ast::BackRef {
+                group: 2,
+                ignore_case: false
+            }
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
     #[test]
     fn test_splice() {
         let a = '0';
@@ -428,14 +1069,12 @@ mod tests {
         let aa = a as usize;
         let zz = z as usize;
         eprintln!("{aa} --> {zz}");
-        let v: Vec<char> = (a..=z).into_iter().collect();
+        let v: Vec<char> = (a..=z).collect();
         eprintln!("{:?}", v);
     }
 
     #[test]
     fn test_split() {
-        eprintln!("empty? {}", "".is_empty());
-
         let text = "2,";
         let res: Vec<&str> = text.split(",").collect();
         eprintln!("{:?}", res);
@@ -0,0 +1,691 @@
+use crate::regex::{input::Text, vm::VM, Inst};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{BuildHasherDefault, Hasher},
+    ops::Range,
+};
+
+/// 按优先级保存某一位置上存活的线程。每个线程停在一条会消耗字符的指令或 Match 上，
+/// 带着自己的捕获位置和计数器，第 i 个线程的分别存放在 `slots[i * nslots..]` 和 `counters[i * ncounters..]`。
+/// 每个计数器占两格：已经转过的圈数，以及这一圈是不是从当前位置开始的。
+/// 展开空转移时到过的状态只保留第一次，程序没有计数器时状态就是 pc，否则还要加上各计数器的值。
+/// 计数器的值先换成编号，同一组值只分配一次
+struct Threads {
+    pcs: Vec<usize>,
+    nslots: usize,
+    slots: Vec<Option<usize>>,
+    ncounters: usize,
+    counters: Vec<usize>,
+    seen: Vec<bool>,
+    visited: Vec<usize>,
+    ids: HashMap<Vec<usize>, usize, BuildHasherDefault<StateHasher>>,
+    states: HashSet<(usize, usize), BuildHasherDefault<StateHasher>>,
+}
+
+impl Threads {
+    fn new(len: usize, nslots: usize, ncounters: usize) -> Self {
+        Self {
+            pcs: Vec::new(),
+            nslots,
+            slots: Vec::new(),
+            ncounters,
+            counters: Vec::new(),
+            seen: vec![false; len],
+            visited: Vec::new(),
+            ids: HashMap::default(),
+            states: HashSet::default(),
+        }
+    }
+
+    /// 计数器的一组值对应的编号，没有计数器时总是 0，只有一个时直接由它的两格算出
+    fn counters_id(&mut self, counters: &[usize]) -> usize {
+        match counters {
+            [] => return 0,
+            [count, started] => return count * 2 + started,
+            _ => {}
+        }
+        if let Some(&id) = self.ids.get(counters) {
+            return id;
+        }
+        let id = self.ids.len();
+        self.ids.insert(counters.to_vec(), id);
+        id
+    }
+
+    /// 记下到过的状态，之前已经到过时返回 false。id 是计数器的编号
+    fn visit(&mut self, pc: usize, id: usize) -> bool {
+        if self.ncounters > 0 {
+            return self.states.insert((pc, id));
+        }
+        if self.seen[pc] {
+            return false;
+        }
+        self.seen[pc] = true;
+        self.visited.push(pc);
+        true
+    }
+
+    fn push(&mut self, pc: usize, slots: &[Option<usize>], counters: &[usize]) {
+        self.pcs.push(pc);
+        self.slots.extend_from_slice(slots);
+        self.counters.extend_from_slice(counters);
+    }
+
+    fn slots(&self, i: usize) -> &[Option<usize>] {
+        &self.slots[i * self.nslots..(i + 1) * self.nslots]
+    }
+
+    fn counters(&self, i: usize) -> &[usize] {
+        &self.counters[i * self.ncounters..(i + 1) * self.ncounters]
+    }
+
+    fn clear(&mut self) {
+        for &pc in self.visited.iter() {
+            self.seen[pc] = false;
+        }
+        self.visited.clear();
+        self.ids.clear();
+        self.states.clear();
+        self.pcs.clear();
+        self.slots.clear();
+        self.counters.clear();
+    }
+}
+
+/// 状态都是几个整数，用乘法散列，比默认的 SipHash 快得多
+#[derive(Default)]
+struct StateHasher(u64);
+
+impl Hasher for StateHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0.rotate_left(5) ^ n).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// 展开空转移时的待办事项，捕获位置、计数器和经过的指令在回退时要恢复
+enum Frame {
+    Explore(usize),
+    // 进入第 slot 个计数器循环的循环体，记下这一圈从当前位置开始
+    Enter(usize, usize),
+    // 离开第 slot 个计数器循环，清零计数器后从 pc 继续
+    Exit(usize, usize),
+    Restore(usize, Option<usize>),
+    RestoreCounter(usize, usize),
+    Leave(usize),
+    // 重新进入计数器循环时清掉的标记，回退时恢复
+    Reenter(usize),
+}
+
+/// 展开空转移时用的临时空间，在各个位置之间复用
+struct Scratch {
+    stack: Vec<Frame>,
+    // 当前路径在这个位置上经过了哪些指令
+    entered: Vec<bool>,
+    caps: Vec<Option<usize>>,
+    counters: Vec<usize>,
+    // 下标是 pc，这一步已经从它开始展开的线程的计数器，一个接一个存放
+    seeds: Vec<Vec<usize>>,
+    seeded: Vec<usize>,
+}
+
+impl Scratch {
+    fn new(len: usize, nslots: usize, ncounters: usize) -> Self {
+        Self {
+            stack: Vec::new(),
+            entered: vec![false; len],
+            caps: vec![None; nslots],
+            counters: vec![0; ncounters],
+            seeds: vec![Vec::new(); if ncounters > 0 { len + 1 } else { 0 }],
+            seeded: Vec::new(),
+        }
+    }
+
+    fn clear_seeds(&mut self) {
+        for &pc in self.seeded.iter() {
+            self.seeds[pc].clear();
+        }
+        self.seeded.clear();
+    }
+}
+
+/// Pike VM：所有线程在输入上同步前进，每个位置每个状态最多一个线程，
+/// 因此没有计数器时匹配时间与 `指令数 * 文本长度` 成正比，不会递归也不会指数爆炸。
+/// 不支持反向引用和原子分组。
+pub struct PikeVM<'r> {
+    instrs: &'r [Inst],
+    // 计数器的个数
+    ncounters: usize,
+    // 各计数器循环的 min、max 和是否贪婪
+    bounds: Vec<(usize, usize, bool)>,
+    // 能从不止一处到达的 pc，展开时只有它们需要去重
+    joins: Vec<bool>,
+}
+
+impl<'r> PikeVM<'r> {
+    pub fn new(instrs: &'r [Inst]) -> Self {
+        let mut bounds = vec![];
+        for inst in instrs {
+            if let Inst::Repeat {
+                slot,
+                min,
+                max,
+                greedy,
+                ..
+            } = inst
+            {
+                if bounds.len() <= *slot {
+                    bounds.resize(slot + 1, (0, 0, true));
+                }
+                bounds[*slot] = (*min, *max, *greedy);
+            }
+        }
+        Self {
+            instrs,
+            ncounters: bounds.len(),
+            bounds,
+            joins: Self::joins(instrs),
+        }
+    }
+
+    /// 统计每条指令能从几处到达：空转移、上一个位置消耗的字符，以及每个位置新启动的线程。
+    /// 只有一处来源的指令，来源那里已经去过重，不会以同样的状态再次到达
+    fn joins(instrs: &[Inst]) -> Vec<bool> {
+        let mut sources = vec![0usize; instrs.len() + 1];
+        sources[0] += 1;
+        for (pc, inst) in instrs.iter().enumerate() {
+            let targets = match inst {
+                Inst::Jump(offset) if *offset < 0 => {
+                    let head = VM::jump_by(pc, *offset);
+                    [Some(head), VM::loop_exit(instrs, head, 0)]
+                }
+                Inst::Jump(offset) => [Some(VM::jump_by(pc, *offset)), None],
+                Inst::Split(offset1, offset2) => [
+                    Some(VM::jump_by(pc, *offset1)),
+                    Some(VM::jump_by(pc, *offset2)),
+                ],
+                Inst::Repeat { len, .. } => [Some(pc + 1), Some(pc + len)],
+                Inst::RepeatNext { offset, .. } => {
+                    let head = VM::jump_by(pc, *offset);
+                    let exit = match instrs.get(head) {
+                        Some(Inst::Repeat { len, .. }) => Some(head + len),
+                        _ => None,
+                    };
+                    [Some(head), exit]
+                }
+                Inst::Match => [None, None],
+                _ => [Some(pc + 1), None],
+            };
+            for target in targets.into_iter().flatten() {
+                if let Some(count) = sources.get_mut(target) {
+                    *count += 1;
+                }
+            }
+        }
+        sources.iter().map(|&count| count > 1).collect()
+    }
+
+    /// 程序中没有只能靠回溯实现的指令时，才能交给 Pike VM
+    pub fn supports(instrs: &[Inst]) -> bool {
+        !instrs.iter().any(Inst::needs_backtrack)
+    }
+
+    /// 线程消耗一个字符、来到还剩 rest 个字节的位置时整理它的计数器：新位置上还没有开始新的一圈；
+    /// 已经够 min 圈、剩下的输入又不够转到 max 圈的计数器，之后怎么走都和刚好 min 圈时一样，
+    /// 改成 min 让这样的线程能合并，否则每个起点的圈数都不同，线程数会随输入长度增长
+    fn advance(&self, counters: &mut [usize], rest: usize) {
+        for (slot, &(min, max, _)) in self.bounds.iter().enumerate() {
+            let count = &mut counters[slot * 2];
+            // 每一圈至少消耗一个字节，空的一圈之后就离开循环，所以最多还能再数 rest 圈
+            if *count >= min && max - *count > rest {
+                *count = min;
+            }
+            counters[slot * 2 + 1] = 0;
+        }
+    }
+
+    /// 线程消耗一个字符后要从 pc 开始展开，计数器已经在 `scratch.counters` 里。
+    /// 这一步里优先级更高、在同一个 pc 上的线程之后能走的路它都能走时，这个线程找到的匹配不会被采用，
+    /// 不用展开，返回 false。否则记下它，返回 true
+    fn seed(&self, scratch: &mut Scratch, pc: usize, rest: usize) -> bool {
+        self.advance(&mut scratch.counters, rest);
+        if self.ncounters == 0 {
+            return true;
+        }
+        let seeds = &mut scratch.seeds[pc];
+        let width = scratch.counters.len();
+        if seeds
+            .chunks(width)
+            .any(|seed| self.dominates(seed, &scratch.counters, rest))
+        {
+            return false;
+        }
+        if seeds.is_empty() {
+            scratch.seeded.push(pc);
+        }
+        seeds.extend_from_slice(&scratch.counters);
+        true
+    }
+
+    /// 和 `seed` 一样，但不管优先级：被已有的支配时丢掉，否则加入并去掉被它支配的
+    fn offer(&self, scratch: &mut Scratch, pc: usize, rest: usize) {
+        self.advance(&mut scratch.counters, rest);
+        let (counters, seeds) = (&scratch.counters, &mut scratch.seeds[pc]);
+        let width = counters.len();
+        if seeds
+            .chunks(width)
+            .any(|seed| self.dominates(seed, counters, rest))
+        {
+            return;
+        }
+        if seeds.is_empty() {
+            scratch.seeded.push(pc);
+        }
+        let mut i = 0;
+        while i < seeds.len() {
+            if self.dominates(counters, &seeds[i..i + width], rest) {
+                seeds.drain(i..i + width);
+            } else {
+                i += width;
+            }
+        }
+        seeds.extend_from_slice(counters);
+    }
+
+    /// 在还剩 rest 个字节的位置，带着计数器 a 的线程是否能走带着 b 时能走的每一条路。
+    /// 两边的圈数相同；或者 a 已经够 min 圈且不比 b 多，之后需要的圈数只会更少；
+    /// 或者贪婪的循环里 a 不比 b 少，剩下的输入又不够 a 转到 max 圈
+    fn dominates(&self, a: &[usize], b: &[usize], rest: usize) -> bool {
+        self.bounds
+            .iter()
+            .enumerate()
+            .all(|(slot, &(min, max, greedy))| {
+                let (a, b) = (a[slot * 2], b[slot * 2]);
+                a == b || (a >= min && a <= b) || (greedy && a >= b && max - a > rest)
+            })
+    }
+
+    pub fn is_match(&self, text: &Text) -> bool {
+        let len = self.instrs.len();
+        let mut clist = Threads::new(len, 0, self.ncounters * 2);
+        let mut nlist = Threads::new(len, 0, self.ncounters * 2);
+        let mut scratch = Scratch::new(len, 0, self.ncounters * 2);
+        let mut cursor = 0;
+
+        loop {
+            // 每个位置都从头启动一个新线程，相当于同时尝试所有起点
+            scratch.counters.fill(0);
+            self.add_thread(&mut clist, &mut scratch, 0, text, cursor);
+
+            let ch = text.char_at(cursor);
+            let next = cursor + ch.map_or(0, char::len_utf8);
+            for (i, &pc) in clist.pcs.iter().enumerate() {
+                match &self.instrs[pc] {
+                    Inst::Match => return true,
+                    inst if inst.is_consuming() && ch.is_some_and(|c| inst.is_match(&c)) => {
+                        if self.ncounters == 0 {
+                            self.add_thread(&mut nlist, &mut scratch, pc + 1, text, next);
+                        } else {
+                            scratch.counters.copy_from_slice(clist.counters(i));
+                            self.offer(&mut scratch, pc + 1, text.text().len() - next);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            // 只问有没有匹配时不用管优先级，每个 pc 上只展开谁也支配不了的计数器
+            for j in 0..scratch.seeded.len() {
+                let pc = scratch.seeded[j];
+                let seeds = std::mem::take(&mut scratch.seeds[pc]);
+                for seed in seeds.chunks(scratch.counters.len()) {
+                    scratch.counters.copy_from_slice(seed);
+                    self.add_thread(&mut nlist, &mut scratch, pc, text, next);
+                }
+                scratch.seeds[pc] = seeds;
+            }
+
+            let Some(c) = ch else {
+                return false;
+            };
+            cursor += c.len_utf8();
+            std::mem::swap(&mut clist, &mut nlist);
+            nlist.clear();
+            scratch.clear_seeds();
+        }
+    }
+
+    /// 从 `start` 开始找最左边的匹配，同一起点按分支优先级取第一个（leftmost-first）。
+    /// 匹配成功时把捕获位置写进 `slots`：第 n 组的起止在 `2n` 和 `2n+1`，第 0 组是整个匹配
+    pub fn search(&self, text: &Text, start: usize, slots: &mut [Option<usize>]) -> bool {
+        let (len, nslots) = (self.instrs.len(), slots.len());
+        let mut clist = Threads::new(len, nslots, self.ncounters * 2);
+        let mut nlist = Threads::new(len, nslots, self.ncounters * 2);
+        let mut scratch = Scratch::new(len, nslots, self.ncounters * 2);
+        let mut matched = false;
+        let mut cursor = start;
+
+        loop {
+            // 已经找到匹配后就不再尝试更靠右的起点
+            if !matched {
+                scratch.caps.fill(None);
+                if let Some(slot) = scratch.caps.first_mut() {
+                    *slot = Some(cursor);
+                }
+                scratch.counters.fill(0);
+                self.add_thread(&mut clist, &mut scratch, 0, text, cursor);
+            } else if clist.pcs.is_empty() {
+                break;
+            }
+
+            let ch = text.char_at(cursor);
+            for (i, &pc) in clist.pcs.iter().enumerate() {
+                match &self.instrs[pc] {
+                    Inst::Match => {
+                        // 优先级更低的线程全部丢弃
+                        slots.copy_from_slice(clist.slots(i));
+                        if let Some(slot) = slots.get_mut(1) {
+                            *slot = Some(cursor);
+                        }
+                        matched = true;
+                        break;
+                    }
+                    inst if inst.is_consuming() && ch.is_some_and(|c| inst.is_match(&c)) => {
+                        scratch.caps.copy_from_slice(clist.slots(i));
+                        scratch.counters.copy_from_slice(clist.counters(i));
+                        let next = cursor + ch.map_or(0, char::len_utf8);
+                        if !self.seed(&mut scratch, pc + 1, text.text().len() - next) {
+                            continue;
+                        }
+                        self.add_thread(&mut nlist, &mut scratch, pc + 1, text, next);
+                    }
+                    _ => {}
+                }
+            }
+
+            let Some(c) = ch else {
+                break;
+            };
+            cursor += c.len_utf8();
+            std::mem::swap(&mut clist, &mut nlist);
+            nlist.clear();
+            scratch.clear_seeds();
+        }
+
+        matched
+    }
+
+    /// 沿着空转移（Jump/Split/分组/锚点/计数器）展开，只把会消耗字符的指令和 Match 放进线程列表，
+    /// 同时记下线程走到这里时的捕获位置和计数器
+    fn add_thread(
+        &self,
+        list: &mut Threads,
+        scratch: &mut Scratch,
+        pc: usize,
+        text: &Text,
+        cursor: usize,
+    ) {
+        let Scratch {
+            stack,
+            entered,
+            caps,
+            counters,
+            ..
+        } = scratch;
+        // 计数器当前的值在 list 里的编号，计数器改变后要重新查
+        let mut id = None;
+        stack.push(Frame::Explore(pc));
+        while let Some(frame) = stack.pop() {
+            let pc = match frame {
+                Frame::Explore(pc) => pc,
+                Frame::Enter(slot, pc) => {
+                    Self::set_counter(stack, counters, slot * 2 + 1, 1);
+                    id = None;
+                    pc
+                }
+                Frame::Exit(slot, pc) => {
+                    // 离开循环后计数器不再有用，清零以免只差在它上面的线程不能合并
+                    Self::set_counter(stack, counters, slot * 2, 0);
+                    Self::set_counter(stack, counters, slot * 2 + 1, 0);
+                    id = None;
+                    pc
+                }
+                Frame::Restore(slot, old) => {
+                    caps[slot] = old;
+                    continue;
+                }
+                Frame::RestoreCounter(slot, old) => {
+                    counters[slot] = old;
+                    id = None;
+                    continue;
+                }
+                Frame::Leave(pc) => {
+                    entered[pc] = false;
+                    continue;
+                }
+                Frame::Reenter(pc) => {
+                    entered[pc] = true;
+                    continue;
+                }
+            };
+            // 和回溯的 VM 一样，同一条路径在这个位置上不会两次经过同一条指令
+            if entered[pc] {
+                continue;
+            }
+            if self.joins[pc] {
+                let state = *id.get_or_insert_with(|| list.counters_id(counters));
+                if !list.visit(pc, state) {
+                    continue;
+                }
+            }
+            // 这条路径展开完之后才离开
+            entered[pc] = true;
+            stack.push(Frame::Leave(pc));
+            match &self.instrs[pc] {
+                Inst::Jump(offset) if *offset < 0 => {
+                    // 这一圈没有消耗字符时和回溯的 VM 一样改走循环的出口，出口已经试过就放弃
+                    let head = VM::jump_by(pc, *offset);
+                    if !entered[head] {
+                        stack.push(Frame::Explore(head));
+                    } else if let Some(exit) = VM::loop_exit(self.instrs, head, 0) {
+                        stack.push(Frame::Explore(exit));
+                    }
+                }
+                Inst::Jump(offset) => stack.push(Frame::Explore(VM::jump_by(pc, *offset))),
+                Inst::Split(offset1, offset2) => {
+                    // 后压入的先展开，保证第一个分支优先
+                    stack.push(Frame::Explore(VM::jump_by(pc, *offset2)));
+                    stack.push(Frame::Explore(VM::jump_by(pc, *offset1)));
+                }
+                Inst::GroupBegin(num) => {
+                    Self::save_slot(stack, caps, num * 2, cursor);
+                    stack.push(Frame::Explore(pc + 1));
+                }
+                Inst::GroupEnd(num) => {
+                    Self::save_slot(stack, caps, num * 2 + 1, cursor);
+                    stack.push(Frame::Explore(pc + 1));
+                }
+                Inst::RepeatStart(slot) => {
+                    Self::set_counter(stack, counters, slot * 2, 0);
+                    Self::set_counter(stack, counters, slot * 2 + 1, 0);
+                    id = None;
+                    Self::forget_entered(stack, entered, VM::counted_loop(self.instrs, pc));
+                    stack.push(Frame::Explore(pc + 1));
+                }
+                Inst::Repeat {
+                    slot,
+                    min,
+                    max,
+                    greedy,
+                    len,
+                } => {
+                    let count = counters[slot * 2];
+                    let (body, exit) = (pc + 1, pc + len);
+                    if count < *min {
+                        stack.push(Frame::Enter(*slot, body));
+                    } else if count >= *max {
+                        stack.push(Frame::Exit(*slot, exit));
+                    } else if *greedy {
+                        stack.push(Frame::Exit(*slot, exit));
+                        stack.push(Frame::Enter(*slot, body));
+                    } else {
+                        stack.push(Frame::Enter(*slot, body));
+                        stack.push(Frame::Exit(*slot, exit));
+                    }
+                }
+                Inst::RepeatNext { slot, offset } => {
+                    // 和回溯的 VM 一样，空的一圈之后直接离开循环
+                    let count = counters[slot * 2];
+                    let head = VM::jump_by(pc, *offset);
+                    if counters[slot * 2 + 1] == 0 {
+                        Self::set_counter(stack, counters, slot * 2, count + 1);
+                        id = None;
+                        Self::forget_entered(stack, entered, head + 1..pc + 1);
+                        stack.push(Frame::Explore(head));
+                    } else if let Some(exit) = VM::loop_exit(self.instrs, head, count) {
+                        stack.push(Frame::Exit(*slot, exit));
+                    }
+                }
+                Inst::Start if cursor == 0 => stack.push(Frame::Explore(pc + 1)),
+                Inst::End if text.is_end(cursor) => stack.push(Frame::Explore(pc + 1)),
+                Inst::StartLine if text.is_line_start(cursor) => stack.push(Frame::Explore(pc + 1)),
+                Inst::EndLine if text.is_line_end(cursor) => stack.push(Frame::Explore(pc + 1)),
+                Inst::EndBeforeNewline if text.is_end_before_newline(cursor) => {
+                    stack.push(Frame::Explore(pc + 1))
+                }
+                Inst::WordBoundary if text.is_word_boundary(cursor) => {
+                    stack.push(Frame::Explore(pc + 1))
+                }
+                Inst::NotWordBoundary if !text.is_word_boundary(cursor) => {
+                    stack.push(Frame::Explore(pc + 1))
+                }
+                inst if inst.is_consuming() || matches!(inst, Inst::Match) => {
+                    list.push(pc, caps, counters);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// 修改捕获位置，并安排在这条路径展开完之后恢复原值
+    fn save_slot(stack: &mut Vec<Frame>, caps: &mut [Option<usize>], slot: usize, cursor: usize) {
+        if let Some(old) = caps.get_mut(slot) {
+            stack.push(Frame::Restore(slot, *old));
+            *old = Some(cursor);
+        }
+    }
+
+    /// 新的一轮循环里 range 中的指令不算经过过，这条路径展开完之后恢复
+    fn forget_entered(stack: &mut Vec<Frame>, entered: &mut [bool], range: Range<usize>) {
+        for pc in range {
+            if entered[pc] {
+                entered[pc] = false;
+                stack.push(Frame::Reenter(pc));
+            }
+        }
+    }
+
+    /// 修改计数器，并安排在这条路径展开完之后恢复原值
+    fn set_counter(stack: &mut Vec<Frame>, counters: &mut [usize], slot: usize, count: usize) {
+        let old = std::mem::replace(&mut counters[slot], count);
+        stack.push(Frame::RestoreCounter(slot, old));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regex::{compiler::DEFAULT_SIZE_LIMIT, input::Text, parser::Parser, pikevm::PikeVM};
+
+    #[test]
+    fn test_pathological_alternation() {
+        let instrs = Parser::new("(a|a)*b")
+            .compile(DEFAULT_SIZE_LIMIT)
+            .unwrap()
+            .instrs;
+        let input = "a".repeat(64);
+        let text = Text::new(&input);
+        assert!(!PikeVM::new(&instrs).is_match(&text));
+
+        let input = format!("{}b", "a".repeat(64));
+        let text = Text::new(&input);
+        assert!(PikeVM::new(&instrs).is_match(&text));
+    }
+
+    #[test]
+    fn test_long_line() {
+        let instrs = Parser::new("a*b")
+            .compile(DEFAULT_SIZE_LIMIT)
+            .unwrap()
+            .instrs;
+        let input = format!("{}b", "a".repeat(1_000_000));
+        let text = Text::new(&input);
+        assert!(PikeVM::new(&instrs).is_match(&text));
+    }
+
+    #[test]
+    fn test_search() {
+        let instrs = Parser::new("a+b")
+            .compile(DEFAULT_SIZE_LIMIT)
+            .unwrap()
+            .instrs;
+        let vm = PikeVM::new(&instrs);
+        let text = Text::new("xxaab ab");
+        let mut slots = [None; 2];
+        assert!(vm.search(&text, 0, &mut slots));
+        assert_eq!(slots, [Some(2), Some(5)]);
+        assert!(vm.search(&text, 3, &mut slots));
+        assert_eq!(slots, [Some(3), Some(5)]);
+        assert!(vm.search(&text, 5, &mut slots));
+        assert_eq!(slots, [Some(6), Some(8)]);
+        assert!(!vm.search(&text, 7, &mut slots));
+    }
+
+    #[test]
+    fn test_search_captures() {
+        let instrs = Parser::new("(a|ab)(c|bcd)(d*)")
+            .compile(DEFAULT_SIZE_LIMIT)
+            .unwrap()
+            .instrs;
+        let vm = PikeVM::new(&instrs);
+        let text = Text::new("abcd");
+        let mut slots = [None; 8];
+        assert!(vm.search(&text, 0, &mut slots));
+        assert_eq!(
+            slots,
+            [
+                Some(0),
+                Some(4),
+                Some(0),
+                Some(1),
+                Some(1),
+                Some(4),
+                Some(4),
+                Some(4)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_anchors() {
+        let instrs = Parser::new("^ab$")
+            .compile(DEFAULT_SIZE_LIMIT)
+            .unwrap()
+            .instrs;
+        let vm = PikeVM::new(&instrs);
+        assert!(vm.is_match(&Text::new("ab")));
+        assert!(!vm.is_match(&Text::new("cab")));
+        assert!(!vm.is_match(&Text::new("abc")));
+    }
+}
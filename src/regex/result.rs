@@ -1,13 +1,141 @@
+use std::{collections::HashMap, ops::Index, sync::Arc};
+
+/// 一次匹配的结果，`capatured[n]` 是第 n 个捕获组的 `[start, end)`，
+/// 第 0 组总是整个匹配，没有参与匹配的组为 None
+#[derive(Debug, Clone)]
 pub struct MatchResult {
-    is_match: bool,
-    capatured: Vec<(usize, usize)>,
+    capatured: Vec<Option<(usize, usize)>>,
 }
 
 impl MatchResult {
-    pub fn new(is_match: bool, capatured: Vec<(usize, usize)>) -> Self {
+    pub fn new(capatured: Vec<Option<(usize, usize)>>) -> Self {
+        Self { capatured }
+    }
+
+    /// 由 VM 的捕获位置构造，第 n 组的起止在 `slots[2n]` 和 `slots[2n+1]`
+    pub fn from_slots(slots: &[Option<usize>]) -> Self {
+        let capatured = slots
+            .chunks(2)
+            .map(|pair| match pair {
+                [Some(start), Some(end)] if start <= end => Some((*start, *end)),
+                _ => None,
+            })
+            .collect();
+        Self::new(capatured)
+    }
+
+    pub fn group(&self, i: usize) -> Option<(usize, usize)> {
+        self.capatured.get(i).copied().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.capatured.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.capatured.is_empty()
+    }
+}
+
+/// 一次匹配中各个捕获组的内容，第 0 组是整个匹配
+#[derive(Debug, Clone)]
+pub struct Captures<'t> {
+    text: &'t str,
+    result: MatchResult,
+    // 分组名到组号
+    names: Arc<HashMap<String, usize>>,
+}
+
+impl<'t> Captures<'t> {
+    pub(crate) fn new(
+        text: &'t str,
+        result: MatchResult,
+        names: Arc<HashMap<String, usize>>,
+    ) -> Self {
         Self {
-            is_match,
-            capatured,
+            text,
+            result,
+            names,
         }
     }
+
+    /// 第 i 组的匹配，组号越界或该组没有参与匹配时返回 None
+    pub fn get(&self, i: usize) -> Option<Match<'t>> {
+        let (start, end) = self.result.group(i)?;
+        Some(Match::new(self.text, start, end))
+    }
+
+    /// 按名字取分组的匹配
+    pub fn name(&self, name: &str) -> Option<Match<'t>> {
+        self.get(*self.names.get(name)?)
+    }
+
+    /// 捕获组的数量，包括第 0 组
+    pub fn len(&self) -> usize {
+        self.result.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.result.is_empty()
+    }
+}
+
+impl<'t> Index<usize> for Captures<'t> {
+    type Output = str;
+
+    /// 第 i 组没有匹配时 panic，不确定时用 `get`
+    fn index(&self, i: usize) -> &str {
+        self.get(i)
+            .map(|m| m.as_str())
+            .unwrap_or_else(|| panic!("no match for group {}", i))
+    }
+}
+
+impl<'t, 'n> Index<&'n str> for Captures<'t> {
+    type Output = str;
+
+    /// 分组 name 不存在或没有匹配时 panic，不确定时用 `name`
+    fn index(&self, name: &'n str) -> &str {
+        self.name(name)
+            .map(|m| m.as_str())
+            .unwrap_or_else(|| panic!("no match for group '{}'", name))
+    }
+}
+
+/// 一次匹配在原文本中的位置，`start`/`end` 都是字节偏移，左闭右开
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match<'t> {
+    text: &'t str,
+    start: usize,
+    end: usize,
+}
+
+impl<'t> Match<'t> {
+    pub(crate) fn new(text: &'t str, start: usize, end: usize) -> Self {
+        Self { text, start, end }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn as_str(&self) -> &'t str {
+        &self.text[self.start..self.end]
+    }
 }
@@ -0,0 +1,87 @@
+#[rustfmt::skip]
+mod tables;
+
+pub use tables::{PERL_WORD, UNICODE_VERSION, WHITE_SPACE};
+
+/// 按名字查找 Unicode 属性的区间表，支持 `L`、`Letter`、`Greek`，
+/// 以及 `gc=L`、`General_Category=Letter`、`sc=Greek`、`Script=Greek` 这几种写法。
+/// 名字比较时忽略大小写、空格、'_' 和 '-'
+pub fn property(name: &str) -> Option<&'static [(char, char)]> {
+    let (key, value) = match name.split_once(['=', ':']) {
+        Some((key, value)) => (Some(normalize(key)), normalize(value)),
+        None => (None, normalize(name)),
+    };
+    match key.as_deref() {
+        None => find(tables::GENERAL_CATEGORY, &value).or_else(|| find(tables::SCRIPT, &value)),
+        Some("gc" | "generalcategory") => find(tables::GENERAL_CATEGORY, &value),
+        Some("sc" | "script") => find(tables::SCRIPT, &value),
+        Some(_) => None,
+    }
+}
+
+/// 区间按升序排列且互不重叠，二分查找
+pub fn contains(ranges: &[(char, char)], ch: char) -> bool {
+    ranges
+        .binary_search_by(|&(lo, hi)| {
+            if hi < ch {
+                std::cmp::Ordering::Less
+            } else if lo > ch {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn find(
+    table: &'static [(&str, &'static [(char, char)])],
+    name: &str,
+) -> Option<&'static [(char, char)]> {
+    let index = table.binary_search_by(|(key, _)| (*key).cmp(name)).ok()?;
+    Some(table[index].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regex::unicode::*;
+
+    #[test]
+    fn test_property() {
+        let letter = property("L").unwrap();
+        assert_eq!(property("Letter"), Some(letter));
+        assert_eq!(property("gc=letter"), Some(letter));
+        assert!(contains(letter, 'é'));
+        assert!(!contains(letter, '1'));
+
+        let greek = property("Script=Greek").unwrap();
+        assert_eq!(property("greek"), Some(greek));
+        assert!(contains(greek, 'λ'));
+        assert!(!contains(greek, 'l'));
+
+        assert!(contains(property("Han").unwrap(), '中'));
+        assert!(contains(property("Nd").unwrap(), '٣'));
+        assert_eq!(property("sc=Lu"), None);
+        assert_eq!(property("Klingon"), None);
+    }
+
+    #[test]
+    fn test_tables_match_std() {
+        // 标准库换了 Unicode 版本时跳过，重新生成表以后再对照
+        if char::UNICODE_VERSION != UNICODE_VERSION {
+            return;
+        }
+        for ch in (0..=char::MAX as u32).filter_map(char::from_u32) {
+            let word = ch.is_alphanumeric() || ch == '_';
+            assert_eq!(contains(PERL_WORD, ch), word, "{:?}", ch);
+            assert_eq!(contains(WHITE_SPACE, ch), ch.is_whitespace(), "{:?}", ch);
+        }
+    }
+}
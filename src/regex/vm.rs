@@ -1,105 +1,395 @@
-use crate::regex::{input::Text, Inst};
+use crate::regex::{fold::simple_fold, input::Text, Inst};
+use std::ops::Range;
+
+/// 回溯时要做的事：尝试另一个分支，或者撤销一处修改
+enum Frame {
+    Step(usize, usize), // 从 pc 和文本位置继续
+    RestoreSlot(usize, Option<usize>),
+    RestoreVisit(usize, Option<usize>),
+    RestoreCounter(usize, usize),
+    RestoreAll(Vec<Option<usize>>),
+}
 
 pub struct VM<'r> {
-    instrs: &'r Vec<Inst>,
-    // context: Vec<usize>,
-    capatured: Vec<(usize, usize)>,
+    instrs: &'r [Inst],
+    // 捕获组的位置：第 n 组的起止在 2n 和 2n+1
+    capatured: Vec<Option<usize>>,
+    // 执行到 Match 时的文本位置，即匹配的结束位置
+    end: Option<usize>,
+    // 正在执行的子程序要求结束的位置，后顾断言的子程序必须恰好匹配到断言处
+    sub_targets: Vec<Option<usize>>,
+    // 下标是 pc，值是当前路径最近一次执行它时的文本位置
+    visits: Vec<Option<usize>>,
+    // 计数器循环已经转过的圈数，下标是计数器编号
+    counters: Vec<usize>,
 }
 
 impl<'r> VM<'r> {
-    pub fn new(instrs: &'r Vec<Inst>) -> Self {
+    pub fn new(instrs: &'r [Inst]) -> Self {
         Self {
             instrs,
-            //context: Vec::new(),
             capatured: Vec::new(),
+            end: None,
+            sub_targets: Vec::new(),
+            visits: vec![None; instrs.len()],
+            counters: Vec::new(),
+        }
+    }
+
+    /// RepeatStart 之后的整个计数器循环，从 Repeat 到 RepeatNext。
+    /// 外层循环每转一圈都重新进入它，上一次留下的执行位置要清掉，否则同一位置上的新一轮会被当成重复
+    pub fn counted_loop(instrs: &[Inst], start: usize) -> Range<usize> {
+        match instrs.get(start + 1) {
+            Some(Inst::Repeat { len, .. }) => start + 1..start + 1 + len,
+            _ => start + 1..start + 1,
+        }
+    }
+
+    /// 循环开头 head 处的 Split 或 Repeat 跳出循环时去的 pc，count 是计数器循环已经转过的圈数。
+    /// 非贪婪的循环在进入循环体之前已经试过出口，返回 None
+    pub fn loop_exit(instrs: &[Inst], head: usize, count: usize) -> Option<usize> {
+        match instrs.get(head)? {
+            Inst::Split(1, offset) => Some(Self::jump_by(head, *offset)),
+            Inst::Repeat {
+                min, greedy, len, ..
+            } if *greedy || count < *min => Some(head + len),
+            _ => None,
         }
     }
-    pub fn save_context(&mut self, cursor: usize) {
-        self.capatured.push((cursor, 0));
+
+    pub fn end(&self) -> Option<usize> {
+        self.end
+    }
+    /// 第 num 组捕获到的 `[start, end)`
+    pub fn group(&self, group_num: usize) -> Option<(usize, usize)> {
+        let start = self.capatured.get(group_num * 2).copied().flatten()?;
+        let end = self.capatured.get(group_num * 2 + 1).copied().flatten()?;
+        (start <= end).then_some((start, end))
     }
 
-    pub fn fill_back(&mut self, group_num: usize, end: usize) {
-        if let Some((_, e)) = self.capatured.get_mut(group_num - 1) {
-            *e = end;
+    /// 记录捕获位置，返回旧值以便回溯时恢复
+    pub fn save_context(&mut self, slot: usize, cursor: usize) -> Option<usize> {
+        if self.capatured.len() <= slot {
+            self.capatured.resize(slot + 1, None);
         }
+        self.capatured[slot].replace(cursor)
     }
-    pub fn restore_context(&mut self, group_num: usize) {
-        if let Some((_, end)) = self.capatured.get_mut(group_num - 1) {
-            *end = 0
+
+    pub fn restore_context(&mut self, slot: usize, old: Option<usize>) {
+        if let Some(saved) = self.capatured.get_mut(slot) {
+            *saved = old;
         }
     }
     pub fn jump_by(pc: usize, offset: isize) -> usize {
         ((pc as isize) + offset) as usize
     }
 
-    pub fn run(&mut self, pc: usize, text: &Text, cursor: usize) -> bool {
-        match &self.instrs[pc] {
-            Inst::Char(ch) => {
-                text.matchwith_at(cursor, ch)
-                    && self.run(pc + 1, text, text.next_cursor_unsafe(cursor))
+    /// 执行以 SubMatch 结尾的子程序，target 是子程序必须结束的位置。
+    /// 成功时返回子程序结束的位置
+    fn run_sub(
+        &mut self,
+        pc: usize,
+        text: &Text,
+        cursor: usize,
+        target: Option<usize>,
+    ) -> Option<usize> {
+        let mut stack = vec![Frame::Step(pc, cursor)];
+        self.sub_targets.push(target);
+        let found = self.backtrack(&mut stack, text);
+        self.sub_targets.pop();
+        // 子程序成功时栈里还留着没有撤销的修改，它记录的执行位置要恢复，
+        // 下次从别的位置进入时不能再用。捕获由调用方处理，计数器进入循环时会重新清零
+        while let Some(frame) = stack.pop() {
+            if let Frame::RestoreVisit(pc, old) = frame {
+                self.visits[pc] = old;
             }
+        }
+        let end = self.end.take();
+        if found {
+            end
+        } else {
+            None
+        }
+    }
+
+    /// 环视断言的子程序执行完后，决定能否继续往下走。
+    /// 否定的断言成功时子程序里不会有捕获
+    fn look_around(
+        &mut self,
+        stack: &mut Vec<Frame>,
+        found: bool,
+        negate: bool,
+        snapshot: Vec<Option<usize>>,
+    ) -> bool {
+        if found == negate {
+            self.capatured = snapshot;
+            return false;
+        }
+        stack.push(Frame::RestoreAll(snapshot));
+        true
+    }
+
+    /// 后顾断言的子程序已经走到要求结束的位置，再消耗字符就回不来了
+    fn at_target(&self, cursor: usize) -> bool {
+        matches!(self.sub_targets.last(), Some(Some(target)) if cursor >= *target)
+    }
+
+    /// 执行 pc 之前记下当前位置，回溯时恢复。当前路径已经在这个位置执行过它时返回 false：
+    /// 绕了一圈没有消耗字符，Pike VM 也会丢掉这样的线程
+    fn visit(&mut self, stack: &mut Vec<Frame>, pc: usize, cursor: usize) -> bool {
+        let old = self.visits[pc].replace(cursor);
+        if old == Some(cursor) {
+            return false;
+        }
+        stack.push(Frame::RestoreVisit(pc, old));
+        true
+    }
+
+    /// 循环体执行完要跳回 head。如果这一圈没有消耗字符，再转下去也一样，
+    /// 改从循环的出口继续，和 Pike VM 的优先级一致。出口已经试过时这条路径走不通
+    fn loop_back(&self, head: usize, cursor: usize, count: usize) -> Option<usize> {
+        if self.visits.get(head) != Some(&Some(cursor)) {
+            return Some(head);
+        }
+        Self::loop_exit(self.instrs, head, count)
+    }
 
-            Inst::AnyChar => {
-                text.char_at(cursor).is_some()
-                    && self.run(pc + 1, text, text.next_cursor_unsafe(cursor))
+    /// 清掉 range 里在 cursor 处执行过的记录，回溯时恢复
+    fn forget_visits(&mut self, stack: &mut Vec<Frame>, range: Range<usize>, cursor: usize) {
+        for pc in range {
+            if self.visits[pc] == Some(cursor) {
+                self.visits[pc] = None;
+                stack.push(Frame::RestoreVisit(pc, Some(cursor)));
             }
+        }
+    }
 
-            Inst::Start => cursor == 0,
-            Inst::End => text.char_at(cursor).is_none(),
-            Inst::Match => true,
-            Inst::Jump(offset) => self.run(Self::jump_by(pc, *offset), text, cursor),
-            Inst::Split(offset1, offset2) => {
-                self.run(Self::jump_by(pc, *offset1), text, cursor)
-                    || self.run(Self::jump_by(pc, *offset2), text, cursor)
+    fn counter(&self, slot: usize) -> usize {
+        self.counters.get(slot).copied().unwrap_or(0)
+    }
+
+    /// 修改计数器，并安排回溯时恢复
+    fn set_counter(&mut self, stack: &mut Vec<Frame>, slot: usize, count: usize) {
+        if self.counters.len() <= slot {
+            self.counters.resize(slot + 1, 0);
+        }
+        let old = std::mem::replace(&mut self.counters[slot], count);
+        stack.push(Frame::RestoreCounter(slot, old));
+    }
+
+    /// 从 pc 开始回溯匹配。待尝试的分支和要撤销的修改都放在显式的栈里，
+    /// 不随输入长度递归，只有原子分组和环视的子程序会嵌套调用
+    pub fn run(&mut self, pc: usize, text: &Text, cursor: usize) -> bool {
+        self.backtrack(&mut vec![Frame::Step(pc, cursor)], text)
+    }
+
+    /// 不断弹出栈顶的分支或撤销操作，直到走到 Match 或者栈空了。
+    /// 成功时栈里剩下的是还没有尝试的分支和还没有撤销的修改
+    fn backtrack(&mut self, stack: &mut Vec<Frame>, text: &Text) -> bool {
+        while let Some(frame) = stack.pop() {
+            match frame {
+                Frame::Step(pc, cursor) => {
+                    if self.step(stack, pc, text, cursor) {
+                        return true;
+                    }
+                }
+                Frame::RestoreSlot(slot, old) => self.restore_context(slot, old),
+                Frame::RestoreVisit(pc, old) => self.visits[pc] = old,
+                Frame::RestoreAll(snapshot) => self.capatured = snapshot,
+                Frame::RestoreCounter(slot, old) => self.counters[slot] = old,
             }
-            Inst::CharClass { negated, chars } => text.char_at(cursor).is_some_and(|c| {
-                let res = if !negated {
-                    chars.contains(&c)
-                } else {
-                    !chars.contains(&c)
-                };
-                res && self.run(pc + 1, text, text.next_cursor_unsafe(cursor))
-            }),
-            Inst::Digit => text.char_at(cursor).is_some_and(|c| {
-                c.is_digit(10) && self.run(pc + 1, text, text.next_cursor_unsafe(cursor))
-            }),
-            Inst::MetaChar => text.char_at(cursor).is_some_and(|c| {
-                (c.is_alphanumeric() || c == '_')
-                    && self.run(pc + 1, text, text.next_cursor_unsafe(cursor))
-            }),
-            Inst::GroupBegin(num) => {
-                self.save_context(cursor);
-                self.run(pc + 1, text, cursor)
+        }
+        false
+    }
+
+    /// 沿着一条路径往下执行，遇到 Split 把第二个分支留到回溯时再试。
+    /// 走到 Match 返回 true，走不通返回 false
+    fn step(
+        &mut self,
+        stack: &mut Vec<Frame>,
+        mut pc: usize,
+        text: &Text,
+        mut cursor: usize,
+    ) -> bool {
+        let instrs = self.instrs;
+        loop {
+            let Some(inst) = instrs.get(pc) else {
+                return false;
+            };
+            if !self.visit(stack, pc, cursor) {
+                return false;
             }
-            Inst::GroupEnd(num) => {
-                self.fill_back(*num, cursor);
-                // eprintln!("end_pos: {}", cursor);
-                if self.run(pc + 1, text, cursor) {
-                    true
-                } else {
-                    self.restore_context(*num);
-                    false
+            match inst {
+                inst if inst.is_consuming() => match text.char_at(cursor) {
+                    Some(c) if inst.is_match(&c) && !self.at_target(cursor) => {
+                        cursor += c.len_utf8();
+                        pc += 1;
+                    }
+                    _ => return false,
+                },
+                Inst::Start if cursor == 0 => pc += 1,
+                Inst::End if text.is_end(cursor) => pc += 1,
+                Inst::StartLine if text.is_line_start(cursor) => pc += 1,
+                Inst::EndLine if text.is_line_end(cursor) => pc += 1,
+                Inst::EndBeforeNewline if text.is_end_before_newline(cursor) => pc += 1,
+                Inst::WordBoundary if text.is_word_boundary(cursor) => pc += 1,
+                Inst::NotWordBoundary if !text.is_word_boundary(cursor) => pc += 1,
+                Inst::Match => {
+                    self.end = Some(cursor);
+                    return true;
                 }
-            }
-            Inst::Ref(num) => {
-                if let Some((start, end)) = self.capatured.get(num - 1) {
-                    let rest = &text.text()[cursor..];
-                    let capatured_group = &text.text()[*start..*end];
-                    if rest.starts_with(capatured_group) {
-                        self.run(pc + 1, text, cursor + capatured_group.chars().count())
+                Inst::SubMatch => match self.sub_targets.last() {
+                    Some(Some(target)) if *target != cursor => return false,
+                    _ => {
+                        self.end = Some(cursor);
+                        return true;
+                    }
+                },
+                Inst::Jump(offset) => {
+                    let target = Self::jump_by(pc, *offset);
+                    // 只有循环会往回跳
+                    if *offset < 0 {
+                        let Some(next) = self.loop_back(target, cursor, 0) else {
+                            return false;
+                        };
+                        pc = next;
+                    } else {
+                        pc = target;
+                    }
+                }
+                Inst::Split(offset1, offset2) => {
+                    stack.push(Frame::Step(Self::jump_by(pc, *offset2), cursor));
+                    pc = Self::jump_by(pc, *offset1);
+                }
+                Inst::GroupBegin(num) | Inst::GroupEnd(num) => {
+                    let slot = if matches!(inst, Inst::GroupBegin(_)) {
+                        num * 2
                     } else {
-                        false
+                        num * 2 + 1
+                    };
+                    let old = self.save_context(slot, cursor);
+                    stack.push(Frame::RestoreSlot(slot, old));
+                    pc += 1;
+                }
+                Inst::Ref(num) => {
+                    let Some((start, end)) = self.group(*num) else {
+                        return false;
+                    };
+                    let rest = text.slice(cursor, text.text().len());
+                    let capatured_group = text.slice(start, end);
+                    if !rest.starts_with(capatured_group) {
+                        return false;
+                    }
+                    cursor += capatured_group.len();
+                    pc += 1;
+                }
+                Inst::RefIgnoreCase(num) => {
+                    let Some((start, end)) = self.group(*num) else {
+                        return false;
+                    };
+                    // 等价字符的 UTF-8 长度可能不同，要逐个字符比较
+                    for expected in text.slice(start, end).chars() {
+                        match text.char_at(cursor) {
+                            Some(c) if simple_fold(c) == simple_fold(expected) => {
+                                cursor += c.len_utf8()
+                            }
+                            _ => return false,
+                        }
+                    }
+                    pc += 1;
+                }
+                Inst::Atomic(len) => {
+                    // 子程序只取第一个成功的结果，后面失败也不再回头尝试其它走法
+                    let snapshot = self.capatured.clone();
+                    let Some(after) = self.run_sub(pc + 1, text, cursor, None) else {
+                        return false;
+                    };
+                    stack.push(Frame::RestoreAll(snapshot));
+                    cursor = after;
+                    pc += len;
+                }
+                Inst::LookAhead { negate, len } => {
+                    let snapshot = self.capatured.clone();
+                    let found = self.run_sub(pc + 1, text, cursor, None).is_some();
+                    if !self.look_around(stack, found, *negate, snapshot) {
+                        return false;
+                    }
+                    pc += len;
+                }
+                Inst::LookBehind {
+                    negate,
+                    len,
+                    min,
+                    max,
+                } => {
+                    // 从近到远尝试长度在 [min, max] 之间的起点
+                    let snapshot = self.capatured.clone();
+                    let mut found = false;
+                    let mut start = Some(cursor);
+                    for steps in 0..=*max {
+                        let Some(s) = start else {
+                            break;
+                        };
+                        if steps >= *min && self.run_sub(pc + 1, text, s, Some(cursor)).is_some() {
+                            found = true;
+                            break;
+                        }
+                        start = text.prev_cursor(s);
+                    }
+                    if !self.look_around(stack, found, *negate, snapshot) {
+                        return false;
+                    }
+                    pc += len;
+                }
+                Inst::RepeatStart(slot) => {
+                    self.set_counter(stack, *slot, 0);
+                    self.forget_visits(stack, Self::counted_loop(instrs, pc), cursor);
+                    pc += 1;
+                }
+                Inst::Repeat {
+                    slot,
+                    min,
+                    max,
+                    greedy,
+                    len,
+                } => {
+                    let count = self.counter(*slot);
+                    let (body, exit) = (pc + 1, pc + len);
+                    if count < *min {
+                        pc = body;
+                    } else if count >= *max {
+                        pc = exit;
+                    } else if *greedy {
+                        stack.push(Frame::Step(exit, cursor));
+                        pc = body;
+                    } else {
+                        stack.push(Frame::Step(body, cursor));
+                        pc = exit;
+                    }
+                }
+                Inst::RepeatNext { slot, offset } => {
+                    // 空的一圈之后，剩下必须转的圈也都可以是空的，所以同样直接离开循环
+                    let count = self.counter(*slot);
+                    let target = Self::jump_by(pc, *offset);
+                    let Some(next) = self.loop_back(target, cursor, count) else {
+                        return false;
+                    };
+                    if next == target {
+                        // 新的一圈计数器不同，循环体在这个位置上留下的执行位置不算重复
+                        self.set_counter(stack, *slot, count + 1);
+                        self.forget_visits(stack, target + 1..pc + 1, cursor);
                     }
-                } else {
-                    false
+                    pc = next;
                 }
+                // 不成立的断言
+                _ => return false,
             }
-            _ => todo!("没有实现的指令"),
         }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use crate::regex::{input::Text, vm::VM, Inst};
 
@@ -134,11 +424,11 @@ mod tests {
     fn test_backref2() {
         let instrs: Vec<Inst> = vec![
             Inst::GroupBegin(1),
-            Inst::Split(2, 6),
+            Inst::Split(1, 5),
             Inst::Char('f'), // 2
             Inst::Char('o'),
             Inst::Char('o'),
-            Inst::Jump(9),
+            Inst::Jump(4),
             Inst::Char('b'), // 6
             Inst::Char('a'),
             Inst::Char('r'),